@@ -0,0 +1,94 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use score_websites::ddr_song::DDRSong;
+use score_websites::search::fuzzy::FuzzyConfig;
+use score_websites::search::{SearchIndex, SearchQuery};
+use score_websites::website_backends::sanbai::SanbaiSong;
+
+const WORDS: [&str; 24] = [
+    "love",
+    "dance",
+    "night",
+    "star",
+    "heart",
+    "dream",
+    "fire",
+    "sky",
+    "max",
+    "paranoia",
+    "revolution",
+    "evolved",
+    "trip",
+    "machine",
+    "tokyo",
+    "summer",
+    "angel",
+    "light",
+    "rain",
+    "beat",
+    "magic",
+    "wind",
+    "ドリーム",
+    "エンジェル",
+];
+
+/// A song list about as big as the real one, with names made of common words
+fn synthetic_song_list(num_songs: usize) -> Vec<DDRSong> {
+    let id_alphabet = b"01689DIOPQbdiloq";
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    (0..num_songs)
+        .map(|n| {
+            let id: String = (0..32)
+                .map(|i| id_alphabet[(n >> ((i % 4) * 4)) % 16] as char)
+                .collect();
+            let num_words = 1 + next() as usize % 4;
+            let name = (0..num_words)
+                .map(|_| WORDS[next() as usize % WORDS.len()])
+                .collect::<Vec<_>>()
+                .join(" ");
+            let level = 1 + next() % 19;
+            let json = format!(
+                r#"{{"song_id": "{}", "song_name": "{} {}", "version_num": 19,
+                    "ratings": [3, 7, 11, {}, 0, 7, 11, {}, 0]}}"#,
+                id, name, n, level, level
+            );
+            let sanbai: SanbaiSong = serde_json::from_str(&json).unwrap();
+            DDRSong::new_from_sanbai_and_skillattack(&sanbai, None)
+        })
+        .collect()
+}
+
+pub fn search_benchmark(c: &mut Criterion) {
+    let songs = synthetic_song_list(1500);
+    let index = SearchIndex::new(&songs);
+    let config = FuzzyConfig::default();
+
+    for query in ["paranoia revolution esp", "max 300 esp", "paranoa esp"] {
+        let query = SearchQuery::parse_query(query, false).unwrap();
+        c.bench_function(&format!("scan search `{}`", query_name(&query)), |b| {
+            b.iter(|| query.search_ranked_with(black_box(&songs), &config))
+        });
+        c.bench_function(&format!("indexed search `{}`", query_name(&query)), |b| {
+            b.iter(|| query.search_indexed(&index, black_box(&songs), &config))
+        });
+    }
+    c.bench_function("build search index", |b| {
+        b.iter(|| SearchIndex::new(black_box(&songs)))
+    });
+}
+
+fn query_name<'a>(query: &SearchQuery<'a>) -> &'a str {
+    match query {
+        SearchQuery::ByTitle { song_title, .. } => song_title,
+        SearchQuery::ByArtist { artist, .. } => artist,
+        SearchQuery::ByIdentifier { .. } => "identifier",
+    }
+}
+
+criterion_group!(benches, search_benchmark);
+criterion_main!(benches);
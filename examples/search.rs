@@ -3,10 +3,15 @@ use std::time::Duration;
 
 use anyhow::Result;
 use num_format::{Locale, ToFormattedString};
+use score_websites::cache::BpmCache;
 use score_websites::scores::{LampType, Player};
-use score_websites::search::SearchQuery;
+use score_websites::search::{SearchOutcome, SearchQuery};
+use score_websites::website_backends::patch::SongPatches;
 use tracing_subscriber::EnvFilter;
 
+const BPM_CACHE_PATH: &str = "bpm_cache.json";
+const PATCHES_PATH: &str = "patches.ron";
+
 #[tokio::main]
 async fn main() -> Result<()> {
     setup();
@@ -27,7 +32,12 @@ async fn main() -> Result<()> {
         Player::new(display_name, ddr_code, Some(sanbai_username))
     });
 
-    let db = score_websites::DDRDatabase::new(http.clone(), users).await?;
+    let mut db = score_websites::DDRDatabase::new(http.clone(), users).await?;
+    db.set_bpm_cache(BpmCache::load(BPM_CACHE_PATH).await?);
+    db.set_patches(SongPatches::load(PATCHES_PATH).await?);
+    if db.update_bpms(http.clone(), 8).await > 0 {
+        db.bpm_cache().save(BPM_CACHE_PATH).await?;
+    }
 
     let mut input = String::new();
     loop {
@@ -38,17 +48,36 @@ async fn main() -> Result<()> {
             .expect("Couldn't read line");
         let search = input.trim();
 
-        let query = match SearchQuery::parse_query(&search, false) {
+        let query = match SearchQuery::parse_query(search, false) {
             Ok(query) => query,
-            Err(_) => {
-                println!("Error: Not enough arguments");
-                println!("USAGE: [song name] [difficulty]");
+            Err(e) => {
+                println!("Error: {}", e);
+                println!("USAGE: [song name] [difficulty] [level] [filters]");
+                println!("   or: artist:[artist] [difficulty] [level] [filters]");
                 continue;
             }
         };
 
-        match query.search(db.song_list()) {
-            Some(result) => {
+        // Every song by the artist is as good a match, so list them all
+        if let SearchQuery::ByArtist { .. } = query {
+            let results = db.search(&query);
+            if results.is_empty() {
+                println!("Couldn't find that artist");
+            }
+            for ranked in results {
+                let result = ranked.result;
+                let artist = result.song.artist.as_deref().unwrap_or_default();
+                println!(
+                    "{} / {} | {:?} {}",
+                    result.song.song_name, artist, result.chart, result.level
+                );
+            }
+            continue;
+        }
+
+        match db.search_outcome(&query) {
+            SearchOutcome::Found { best, others } => {
+                let result = best.result;
                 let mut user_song_scores = db
                     .players()
                     .iter()
@@ -64,10 +93,16 @@ async fn main() -> Result<()> {
                     .collect::<Vec<_>>();
                 user_song_scores
                     .sort_by_key(|(_, _, score_row)| Reverse(score_row.map(|s| s.score)));
-                println!(
-                    "{} {:?} ({})",
-                    &result.song.song_name, result.chart, result.level
-                );
+                match result.song.bpm {
+                    Some(bpm) => println!(
+                        "{} {:?} ({}) BPM {}",
+                        &result.song.song_name, result.chart, result.level, bpm
+                    ),
+                    None => println!(
+                        "{} {:?} ({})",
+                        &result.song.song_name, result.chart, result.level
+                    ),
+                }
                 for (code, name, score) in user_song_scores {
                     let (score_str, lamp) = match score {
                         None => ("-".to_string(), ""),
@@ -88,8 +123,37 @@ async fn main() -> Result<()> {
                     };
                     println!("{} | {:8} | {:>9} {}", code, name, score_str, lamp);
                 }
+                let alternatives = others
+                    .iter()
+                    .take(3)
+                    .map(|r| format!("{} ({:.2})", r.result.song.song_name, r.confidence))
+                    .collect::<Vec<_>>();
+                if !alternatives.is_empty() {
+                    println!("Did you mean: {}", alternatives.join(", "));
+                }
+            }
+            SearchOutcome::ChartMissing {
+                song, available, ..
+            } => {
+                let charts = available
+                    .iter()
+                    .map(|(chart, level)| format!("{:?} {}", chart, level))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                println!(
+                    "{} doesn't have that chart, it has {}",
+                    song.song_name, charts
+                );
+            }
+            SearchOutcome::Ambiguous(tied) => {
+                let names = tied
+                    .iter()
+                    .map(|r| r.result.song.song_name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                println!("Which one? {}", names);
             }
-            None => println!("Couldn't find that song"),
+            SearchOutcome::NotFound => println!("Couldn't find that song"),
         }
     }
 
@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use futures::stream::{self, StreamExt};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+use tracing::{info, warn};
+
+use crate::ddr_song::{Bpm, SongId};
+use crate::error::{Error, Result};
+use crate::website_backends::sanbai::song_details::SongDetails;
+use crate::website_backends::sanbai::{jacket_url, JacketSize};
+use crate::HttpClient;
+
+/// A local cache of song BPMs, and the artists that come with them, so they
+/// only need to be fetched from sanbai once.
+///
+/// A cached `None` means sanbai didn't know the BPM of the song when it was fetched.
+/// Songs like that, and songs whose page couldn't be fetched, are fetched again by
+/// [`DDRDatabase::update_bpms`](crate::DDRDatabase::update_bpms) once
+/// [`BpmCache::RETRY_AFTER`] has passed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BpmCache {
+    bpms: HashMap<SongId, CachedSong>,
+    /// The unix timestamp of the last fetch that didn't give a BPM
+    #[serde(default)]
+    failed: HashMap<SongId, i64>,
+}
+
+/// What the [`BpmCache`] keeps from the sanbai song details of a song
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedSong {
+    pub bpm: Option<Bpm>,
+    /// Kept so songs without Skill Attack data still have an artist after the
+    /// song list is refreshed
+    #[serde(default)]
+    pub artist: Option<String>,
+}
+
+impl From<&SongDetails> for CachedSong {
+    fn from(details: &SongDetails) -> Self {
+        Self {
+            bpm: details.bpm,
+            artist: details.artist.clone(),
+        }
+    }
+}
+
+impl BpmCache {
+    /// How long to wait before fetching a song again when the last fetch
+    /// didn't give a BPM
+    pub const RETRY_AFTER: Duration = Duration::DAY;
+
+    /// Loads the cache from a json file. A missing file gives an empty cache
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = match tokio::fs::read_to_string(path).await {
+            Ok(json) => json,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                info!("No bpm cache at {}, starting empty", path.display());
+                return Ok(Self::default());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        serde_json::from_str(&json).map_err(Error::CacheParseError)
+    }
+
+    /// Saves the cache as a json file, overwriting whatever was there
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let json = serde_json::to_string(self).map_err(Error::CacheParseError)?;
+        tokio::fs::write(path, json).await?;
+        Ok(())
+    }
+
+    /// Returns `None` if the song isn't cached, and `Some(None)` if it is
+    /// cached but the BPM is unknown
+    pub fn get(&self, song_id: &SongId) -> Option<Option<Bpm>> {
+        self.bpms.get(song_id).map(|cached| cached.bpm)
+    }
+
+    /// The cached artist of the song, if sanbai had one
+    pub fn artist(&self, song_id: &SongId) -> Option<&str> {
+        self.bpms.get(song_id)?.artist.as_deref()
+    }
+
+    pub fn contains(&self, song_id: &SongId) -> bool {
+        self.bpms.contains_key(song_id)
+    }
+
+    /// Caches a fetched song. A song without a BPM is only fetched again
+    /// after [`BpmCache::RETRY_AFTER`]
+    pub fn insert(&mut self, song_id: SongId, cached: CachedSong, now: OffsetDateTime) {
+        if cached.bpm.is_some() {
+            self.failed.remove(&song_id);
+        } else {
+            self.failed.insert(song_id.clone(), now.unix_timestamp());
+        }
+        self.bpms.insert(song_id, cached);
+    }
+
+    /// Records that the page of the song couldn't be fetched or parsed, so it
+    /// is only fetched again after [`BpmCache::RETRY_AFTER`]
+    pub fn insert_failure(&mut self, song_id: SongId, now: OffsetDateTime) {
+        self.failed.insert(song_id, now.unix_timestamp());
+    }
+
+    /// `true` if we don't have a BPM for the song and haven't failed to get
+    /// one within [`BpmCache::RETRY_AFTER`]
+    pub fn needs_fetch(&self, song_id: &SongId, now: OffsetDateTime) -> bool {
+        if self.get(song_id).flatten().is_some() {
+            return false;
+        }
+        match self.failed.get(song_id) {
+            Some(&failed_at) => {
+                now.unix_timestamp() - failed_at >= Self::RETRY_AFTER.whole_seconds()
+            }
+            None => true,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bpms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bpms.is_empty()
+    }
+}
+
+/// A local directory of song jackets downloaded from sanbai, so they can be
+/// embedded instead of hotlinked.
+///
+/// Jackets are stored as `<dir>/large/<song id>.jpg` and `<dir>/small/<song id>.jpg`
+#[derive(Debug, Clone)]
+pub struct JacketCache {
+    dir: PathBuf,
+}
+
+/// A jacket that is saved in the [`JacketCache`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedJacket {
+    pub song_id: SongId,
+    pub size: JacketSize,
+    pub path: PathBuf,
+}
+
+/// What happened to each jacket requested from [`JacketCache::fetch`]
+#[derive(Debug, Default)]
+pub struct JacketReport {
+    /// Jackets that were downloaded by this call
+    pub downloaded: Vec<CachedJacket>,
+    /// Jackets that were already cached, so weren't downloaded again
+    pub already_cached: Vec<CachedJacket>,
+    /// Jackets sanbai doesn't have
+    pub missing: Vec<(SongId, JacketSize)>,
+    /// Jackets we couldn't download or save for some other reason
+    pub failed: Vec<(SongId, JacketSize, Error)>,
+}
+
+impl JacketReport {
+    /// Every jacket that is now in the cache, whether it was just downloaded or not
+    pub fn cached(&self) -> impl Iterator<Item = &CachedJacket> {
+        self.downloaded.iter().chain(&self.already_cached)
+    }
+}
+
+enum JacketFetch {
+    Downloaded(CachedJacket),
+    AlreadyCached(CachedJacket),
+    Missing(SongId, JacketSize),
+    Failed(SongId, JacketSize, Error),
+}
+
+impl JacketCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Where the jacket of the song is stored, whether it is cached yet or not
+    pub fn path(&self, song_id: &SongId, size: JacketSize) -> PathBuf {
+        let size_dir = match size {
+            JacketSize::Large => "large",
+            JacketSize::Small => "small",
+        };
+        self.dir.join(size_dir).join(format!("{}.jpg", song_id))
+    }
+
+    /// The path of the jacket if it is cached
+    pub async fn cached_path(&self, song_id: &SongId, size: JacketSize) -> Option<PathBuf> {
+        let path = self.path(song_id, size);
+        match tokio::fs::metadata(&path).await {
+            Ok(metadata) if metadata.is_file() => Some(path),
+            _ => None,
+        }
+    }
+
+    /// The bytes of the jacket if it is cached
+    pub async fn read(&self, song_id: &SongId, size: JacketSize) -> Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.path(song_id, size)).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Downloads the jackets of the given songs in each of the given sizes,
+    /// skipping any that are already cached, with at most `max_concurrent`
+    /// requests in flight at once
+    pub async fn fetch(
+        &self,
+        http: HttpClient,
+        song_ids: impl IntoIterator<Item = SongId>,
+        sizes: &[JacketSize],
+        max_concurrent: usize,
+    ) -> JacketReport {
+        let requests: Vec<_> = song_ids
+            .into_iter()
+            .flat_map(|id| sizes.iter().map(move |&size| (id.clone(), size)))
+            .collect();
+
+        let mut report = JacketReport::default();
+        let mut fetches = stream::iter(requests)
+            .map(|(song_id, size)| self.fetch_one(http.clone(), song_id, size))
+            .buffer_unordered(max_concurrent.max(1));
+        while let Some(fetch) = fetches.next().await {
+            match fetch {
+                JacketFetch::Downloaded(jacket) => report.downloaded.push(jacket),
+                JacketFetch::AlreadyCached(jacket) => report.already_cached.push(jacket),
+                JacketFetch::Missing(song_id, size) => report.missing.push((song_id, size)),
+                JacketFetch::Failed(song_id, size, e) => {
+                    warn!("Couldn't fetch {:?} jacket for {}: {:?}", size, song_id, e);
+                    report.failed.push((song_id, size, e));
+                }
+            }
+        }
+        info!(
+            "Jackets: {} downloaded, {} already cached, {} missing, {} failed",
+            report.downloaded.len(),
+            report.already_cached.len(),
+            report.missing.len(),
+            report.failed.len()
+        );
+        report
+    }
+
+    async fn fetch_one(&self, http: HttpClient, song_id: SongId, size: JacketSize) -> JacketFetch {
+        if let Some(path) = self.cached_path(&song_id, size).await {
+            return JacketFetch::AlreadyCached(CachedJacket {
+                song_id,
+                size,
+                path,
+            });
+        }
+        match self.download(http, &song_id, size).await {
+            Ok(Some(path)) => JacketFetch::Downloaded(CachedJacket {
+                song_id,
+                size,
+                path,
+            }),
+            Ok(None) => JacketFetch::Missing(song_id, size),
+            Err(e) => JacketFetch::Failed(song_id, size, e),
+        }
+    }
+
+    /// Returns `None` if sanbai doesn't have the jacket
+    async fn download(
+        &self,
+        http: HttpClient,
+        song_id: &SongId,
+        size: JacketSize,
+    ) -> Result<Option<PathBuf>> {
+        let response = http.get(jacket_url(song_id, size)).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let bytes = response.error_for_status()?.bytes().await?;
+
+        let path = self.path(song_id, size);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // Write to a temporary file first so a half written jacket never
+        // looks like it is cached
+        let partial_path = path.with_extension("jpg.part");
+        tokio::fs::write(&partial_path, &bytes).await?;
+        tokio::fs::rename(&partial_path, &path).await?;
+        Ok(Some(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bpm_cache_round_trip() {
+        let mut cache = BpmCache::default();
+        let now = OffsetDateTime::now_utc();
+        let range: SongId = "6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q".parse().unwrap();
+        let constant: SongId = "0bq9qI9PoPIlQl89bDO60o9q8I1iIP66".parse().unwrap();
+        let unknown: SongId = "ld6P1lbb0bPO9doqbbPOoPb8qoDo8id0".parse().unwrap();
+        let uncached: SongId = "qOlDPoiqibIOqod69dPilbiqD6qdO1qQ".parse().unwrap();
+        let range_bpm = Bpm::Range {
+            lower: 75,
+            upper: 528,
+            main: 264,
+        };
+        cache.insert(
+            range.clone(),
+            CachedSong {
+                bpm: Some(range_bpm),
+                artist: Some("NAOKI".into()),
+            },
+            now,
+        );
+        cache.insert(
+            constant.clone(),
+            CachedSong {
+                bpm: Some(Bpm::Constant(150)),
+                artist: None,
+            },
+            now,
+        );
+        cache.insert(unknown.clone(), CachedSong::default(), now);
+
+        let json = serde_json::to_string(&cache).unwrap();
+        let cache: BpmCache = serde_json::from_str(&json).unwrap();
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.get(&range), Some(Some(range_bpm)));
+        assert_eq!(cache.get(&constant), Some(Some(Bpm::Constant(150))));
+        assert_eq!(cache.get(&unknown), Some(None));
+        assert_eq!(cache.get(&uncached), None);
+        assert_eq!(cache.artist(&range), Some("NAOKI"));
+        assert_eq!(cache.artist(&constant), None);
+        assert!(!cache.needs_fetch(&range, now));
+        assert!(!cache.needs_fetch(&unknown, now));
+        assert!(cache.needs_fetch(&uncached, now));
+    }
+
+    #[test]
+    fn jacket_paths() {
+        let cache = JacketCache::new("jackets");
+        let id: SongId = "6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q".parse().unwrap();
+        assert_eq!(
+            cache.path(&id, JacketSize::Large),
+            Path::new("jackets/large/6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q.jpg")
+        );
+        assert_eq!(
+            cache.path(&id, JacketSize::Small),
+            Path::new("jackets/small/6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q.jpg")
+        );
+    }
+
+    #[test]
+    fn failed_fetches_back_off() {
+        let mut cache = BpmCache::default();
+        let song: SongId = "6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q".parse().unwrap();
+        let now = time::macros::datetime!(2026-10-18 12:00 UTC);
+        let later = now + BpmCache::RETRY_AFTER;
+
+        cache.insert_failure(song.clone(), now);
+        assert!(!cache.needs_fetch(&song, now));
+        assert!(cache.needs_fetch(&song, later));
+
+        // Sanbai not knowing the bpm yet backs off the same way
+        cache.insert(song.clone(), CachedSong::default(), later);
+        assert!(!cache.needs_fetch(&song, later));
+        assert!(cache.needs_fetch(&song, later + BpmCache::RETRY_AFTER));
+
+        let bpm = CachedSong {
+            bpm: Some(Bpm::Constant(150)),
+            artist: None,
+        };
+        cache.insert(song.clone(), bpm, later + BpmCache::RETRY_AFTER);
+        assert!(cache.failed.is_empty());
+        assert!(!cache.needs_fetch(&song, later + BpmCache::RETRY_AFTER * 10));
+    }
+}
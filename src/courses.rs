@@ -1,60 +1,573 @@
-use crate::ddr_song::{Bpm, DDRSong, SongId};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+use crate::ddr_song::{Bpm, Chart, DDRSong, PlayStyle, SongId};
+use crate::error::Error;
+use crate::search::normalize::name_search_forms;
+use crate::website_backends::sanbai::song_details::get_sanbai_song_details_batch;
 use crate::{HttpClient, Result};
-use futures::stream::FuturesOrdered;
+use once_cell::sync::Lazy;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use tokio_stream::StreamExt;
+use tracing::warn;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CourseSerializeInfo {
     pub name: String,
     #[serde(default)]
     pub search_names: Vec<String>,
-    pub songs: Vec<SongId>,
+    pub songs: Vec<CourseSongInfo>,
+    /// Singles or doubles. Dan courses default to their category and course
+    /// trials to singles
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub play_style: Option<PlayStyle>,
+    /// Course trial unless given, or set by the group the course is in for `courses.ron`
+    #[serde(default)]
+    pub category: CourseCategory,
+}
+
+impl CourseSerializeInfo {
+    /// The play style the course is played in
+    pub fn play_style(&self) -> PlayStyle {
+        self.play_style
+            .or(self.category.play_style())
+            .unwrap_or(PlayStyle::Singles)
+    }
+
+    /// The dan level from the name of a dan course, like "8th Dan 八段"
+    pub fn dan_level(&self) -> Option<DanLevel> {
+        match self.category {
+            CourseCategory::CourseTrial => None,
+            CourseCategory::DanSingles | CourseCategory::DanDoubles => self.name.parse().ok(),
+        }
+    }
+}
+
+/// A song of a course and which of its charts is played. Written as just
+/// the song id if the chart isn't known, or with the chart or the level like
+/// `(song: "...", chart: "ESP")` or `(song: "...", level: 14)`.
+///
+/// The chart is moved to the play style of the course, so an `ESP` entry is
+/// `EDP` in a doubles course
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "CourseSongRepr")]
+pub struct CourseSongInfo {
+    pub song: SongId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chart: Option<Chart>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<u8>,
+}
+
+/// Both ways a course song can be written
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CourseSongRepr {
+    Id(SongId),
+    Full {
+        song: SongId,
+        #[serde(default)]
+        chart: Option<Chart>,
+        #[serde(default)]
+        level: Option<u8>,
+    },
+}
+
+impl From<CourseSongRepr> for CourseSongInfo {
+    fn from(repr: CourseSongRepr) -> Self {
+        match repr {
+            CourseSongRepr::Id(song) => song.into(),
+            CourseSongRepr::Full { song, chart, level } => Self { song, chart, level },
+        }
+    }
+}
+
+impl From<SongId> for CourseSongInfo {
+    fn from(song: SongId) -> Self {
+        Self {
+            song,
+            chart: None,
+            level: None,
+        }
+    }
+}
+
+impl CourseSongInfo {
+    /// The chart of the song played in the course, and its current level.
+    ///
+    /// With only a level, the easiest chart of that level is picked. `None`
+    /// if neither is given, or the song doesn't have the chart
+    pub fn resolve(&self, song: &DDRSong, play_style: PlayStyle) -> Option<(Chart, u8)> {
+        let level_of = |chart: Chart| Some(song.ratings.0[chart as usize]).filter(|&l| l != 0);
+        match (self.chart, self.level) {
+            (Some(chart), _) => {
+                let chart = Chart::from_class(chart.class(), play_style)?;
+                Some((chart, level_of(chart)?))
+            }
+            (None, Some(level)) => Chart::iter()
+                .filter(|chart| chart.play_style() == play_style)
+                .find(|&chart| level_of(chart) == Some(level))
+                .map(|chart| (chart, level)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// The kind of course, as grouped in game
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CourseCategory {
+    #[default]
+    CourseTrial,
+    DanSingles,
+    DanDoubles,
+}
+
+impl CourseCategory {
+    /// `None` for course trials, which can be played either way
+    pub fn play_style(&self) -> Option<PlayStyle> {
+        match self {
+            CourseCategory::CourseTrial => None,
+            CourseCategory::DanSingles => Some(PlayStyle::Singles),
+            CourseCategory::DanDoubles => Some(PlayStyle::Doubles),
+        }
+    }
+}
+
+/// The rank of a dan course, from 1st dan to kaiden
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DanLevel {
+    Dan(u8),
+    Kaiden,
+}
+
+impl std::str::FromStr for DanLevel {
+    type Err = ();
+
+    /// Parses the start of a dan course name, like "1st Dan 初段" or "Kaiden 皆伝"
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        static DAN: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r"(?i)^\s*(\d+)(?:st|nd|rd|th)\s+dan\b").unwrap());
+
+        if s.trim_start().to_lowercase().starts_with("kaiden") {
+            return Ok(DanLevel::Kaiden);
+        }
+        let dan = DAN.captures(s).ok_or(())?[1].parse().map_err(|_| ())?;
+        Ok(DanLevel::Dan(dan))
+    }
+}
+
+impl fmt::Display for DanLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DanLevel::Kaiden => write!(f, "Kaiden"),
+            DanLevel::Dan(dan) => {
+                let suffix = match (dan % 10, dan % 100) {
+                    (_, 11..=13) => "th",
+                    (1, _) => "st",
+                    (2, _) => "nd",
+                    (3, _) => "rd",
+                    _ => "th",
+                };
+                write!(f, "{}{} Dan", dan, suffix)
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Course {
     pub name: String,
+    /// The name and every nickname, normalized for searching like song names
     pub search_names: Vec<String>,
-    pub songs: Vec<Option<(DDRSong, Option<Bpm>)>>,
+    /// `None` for songs that aren't in the song list
+    pub songs: Vec<Option<CourseSong>>,
+    pub play_style: PlayStyle,
+    pub category: CourseCategory,
+    pub dan_level: Option<DanLevel>,
+}
+
+/// A song of a [`Course`], resolved against the song list
+#[derive(Debug, Clone)]
+pub struct CourseSong {
+    pub song: DDRSong,
+    pub bpm: Option<Bpm>,
+    /// The chart played and its level, `None` if the course doesn't say or
+    /// the song doesn't have the chart
+    pub chart: Option<(Chart, u8)>,
 }
 
 impl Course {
-    pub async fn new(
-        http: HttpClient,
+    /// Builds a course from the song list, fetching the BPMs the songs don't
+    /// have yet. Songs whose BPM can't be fetched are logged and left
+    /// without one
+    pub async fn new(http: HttpClient, info: CourseSerializeInfo, ddr_songs: &[DDRSong]) -> Self {
+        let max_concurrent = info.songs.len();
+        let bpms = fetch_missing_bpms(http, [&info], ddr_songs, max_concurrent).await;
+        Self::from_song_list(info, ddr_songs, &bpms)
+    }
+
+    /// Builds a course from the song list, taking the BPM of songs without
+    /// one from `fetched_bpms`
+    fn from_song_list(
         info: CourseSerializeInfo,
         ddr_songs: &[DDRSong],
-    ) -> Result<Self> {
-        let mut songs = Vec::with_capacity(4);
-
-        let mut fut: FuturesOrdered<_> = info
+        fetched_bpms: &HashMap<SongId, Bpm>,
+    ) -> Self {
+        let play_style = info.play_style();
+        let songs = info
             .songs
-            .into_iter()
-            .map(|id| {
-                let ddr_song = ddr_songs.iter().find(|s| s.song_id == id).cloned();
-                let http = http.clone();
-                async move {
-                    match ddr_song {
-                        Some(ddr_song) => {
-                            let bpm = ddr_song.fetch_bpm(http).await?;
-                            Result::<_>::Ok(Some((ddr_song, bpm)))
-                        }
-                        None => Ok(None),
-                    }
-                }
+            .iter()
+            .map(|entry| {
+                let ddr_song = ddr_songs.iter().find(|s| s.song_id == entry.song)?;
+                Some(CourseSong {
+                    song: ddr_song.clone(),
+                    bpm: ddr_song
+                        .bpm
+                        .or_else(|| fetched_bpms.get(&entry.song).copied()),
+                    chart: entry.resolve(ddr_song, play_style),
+                })
             })
             .collect();
 
-        while let Some(res) = fut.try_next().await? {
-            songs.push(res);
+        let mut search_names = vec![];
+        for name in info.search_names.iter().chain([&info.name]) {
+            for form in name_search_forms(name) {
+                if !search_names.contains(&form) {
+                    search_names.push(form);
+                }
+            }
         }
-
-        let mut search_names = info.search_names;
-        search_names.push(info.name.to_lowercase());
-        Ok(Self {
+        Self {
+            dan_level: info.dan_level(),
+            play_style,
+            category: info.category,
             name: info.name,
             search_names,
             songs,
-        })
+        }
+    }
+
+    /// The level of the chart played for each song of the course, `None`
+    /// where it isn't known
+    pub fn levels(&self) -> Vec<Option<u8>> {
+        self.songs
+            .iter()
+            .map(|song| Some(song.as_ref()?.chart?.1))
+            .collect()
+    }
+
+    /// The chart and level of each song like "ESP 14, ESP 15, CSP 16, ???"
+    pub fn chart_summary(&self) -> String {
+        self.songs
+            .iter()
+            .map(|song| match song.as_ref().and_then(|s| s.chart) {
+                Some((chart, level)) => format!("{} {}", chart, level),
+                None => "???".to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// The layout of `courses.ron`, with the courses grouped by category
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CourseCatalogFile {
+    #[serde(default)]
+    course_trials: Vec<CourseSerializeInfo>,
+    #[serde(default)]
+    dan_singles: Vec<CourseSerializeInfo>,
+    #[serde(default)]
+    dan_doubles: Vec<CourseSerializeInfo>,
+}
+
+/// Every course we know about, as read from `courses.ron` or `courses.json`
+#[derive(Debug, Clone, Default)]
+pub struct CourseCatalog {
+    pub courses: Vec<CourseSerializeInfo>,
+}
+
+/// A song of a course that isn't in the song list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownCourseSong {
+    pub course: String,
+    pub category: CourseCategory,
+    pub song_id: SongId,
+}
+
+impl CourseCatalog {
+    /// Parses the `courses.ron` format, where courses are grouped into
+    /// `course_trials`, `dan_singles` and `dan_doubles`
+    pub fn from_ron(ron: &str) -> Result<Self> {
+        let file: CourseCatalogFile = ron::from_str(ron).map_err(Error::CourseRonParseError)?;
+        let groups = [
+            (CourseCategory::CourseTrial, file.course_trials),
+            (CourseCategory::DanSingles, file.dan_singles),
+            (CourseCategory::DanDoubles, file.dan_doubles),
+        ];
+        let courses = groups
+            .into_iter()
+            .flat_map(|(category, courses)| {
+                courses
+                    .into_iter()
+                    .map(move |info| CourseSerializeInfo { category, ..info })
+            })
+            .collect();
+        Ok(Self { courses })
+    }
+
+    /// Parses the `courses.json` format, a flat array of courses that are
+    /// course trials unless they have a `category`
+    pub fn from_json(json: &str) -> Result<Self> {
+        let courses = serde_json::from_str(json).map_err(Error::CourseJsonParseError)?;
+        Ok(Self { courses })
+    }
+
+    /// Loads a catalog file, picking the format by the `.ron` or `.json` extension
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = tokio::fs::read_to_string(path).await?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("ron") => Self::from_ron(&contents),
+            Some("json") => Self::from_json(&contents),
+            _ => Err(Error::OtherParseError(
+                "course catalogs have to be .ron or .json files",
+            )),
+        }
+    }
+
+    /// The courses of one category, in catalog order
+    pub fn category(&self, category: CourseCategory) -> impl Iterator<Item = &CourseSerializeInfo> {
+        self.courses.iter().filter(move |c| c.category == category)
+    }
+
+    /// Every song of every course that isn't in the song list, which
+    /// usually means the catalog is out of date or has a typo
+    pub fn unknown_songs(&self, songs: &[DDRSong]) -> Vec<UnknownCourseSong> {
+        let known: HashSet<&SongId> = songs.iter().map(|s| &s.song_id).collect();
+        self.courses
+            .iter()
+            .flat_map(|course| {
+                course
+                    .songs
+                    .iter()
+                    .filter(|entry| !known.contains(&entry.song))
+                    .map(|entry| UnknownCourseSong {
+                        course: course.name.clone(),
+                        category: course.category,
+                        song_id: entry.song.clone(),
+                    })
+            })
+            .collect()
+    }
+
+    /// Builds every course of the catalog, fetching the BPMs the songs don't
+    /// have yet with at most `max_concurrent` requests in flight at once.
+    /// Songs that aren't in the song list are logged and left as `None`, and
+    /// songs whose BPM can't be fetched are logged and left without one
+    pub async fn build_courses(
+        &self,
+        http: HttpClient,
+        songs: &[DDRSong],
+        max_concurrent: usize,
+    ) -> Vec<Course> {
+        for unknown in self.unknown_songs(songs) {
+            warn!(
+                "Course {} ({:?}) has song {} which isn't in the song list",
+                unknown.course, unknown.category, unknown.song_id
+            );
+        }
+        let bpms = fetch_missing_bpms(http, &self.courses, songs, max_concurrent).await;
+        self.courses
+            .iter()
+            .map(|info| Course::from_song_list(info.clone(), songs, &bpms))
+            .collect()
+    }
+}
+
+/// Fetches the BPM of every song of the courses that is in the song list but
+/// doesn't have a BPM yet, each song only once. Failed fetches are logged by
+/// the batch fetch and left out
+async fn fetch_missing_bpms<'a>(
+    http: HttpClient,
+    courses: impl IntoIterator<Item = &'a CourseSerializeInfo>,
+    songs: &[DDRSong],
+    max_concurrent: usize,
+) -> HashMap<SongId, Bpm> {
+    let without_bpm: HashSet<&SongId> = songs
+        .iter()
+        .filter(|s| s.bpm.is_none())
+        .map(|s| &s.song_id)
+        .collect();
+    let missing: HashSet<SongId> = courses
+        .into_iter()
+        .flat_map(|c| &c.songs)
+        .filter(|entry| without_bpm.contains(&entry.song))
+        .map(|entry| entry.song.clone())
+        .collect();
+    if missing.is_empty() {
+        return HashMap::new();
+    }
+    get_sanbai_song_details_batch(http, missing, max_concurrent)
+        .await
+        .into_iter()
+        .filter_map(|(song_id, details)| Some((song_id, details.ok()?.bpm?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn dan_levels() {
+        assert_eq!("1st Dan 初段".parse(), Ok(DanLevel::Dan(1)));
+        assert_eq!("10th Dan 十段".parse(), Ok(DanLevel::Dan(10)));
+        assert_eq!("Kaiden 皆伝".parse(), Ok(DanLevel::Kaiden));
+        assert_eq!("EXCITING WINTER".parse::<DanLevel>(), Err(()));
+        assert!(DanLevel::Dan(10) < DanLevel::Kaiden);
+        assert_eq!(DanLevel::Dan(2).to_string(), "2nd Dan");
+        assert_eq!(DanLevel::Dan(11).to_string(), "11th Dan");
+    }
+
+    #[test]
+    fn chart_accurate_courses() {
+        let catalog = CourseCatalog::from_ron(
+            r#"(
+                dan_doubles: [(
+                    name: "8th Dan 八段",
+                    songs: [
+                        (song: "6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q", chart: "ESP"),
+                        (song: "6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q", level: 17),
+                        (song: "6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q", chart: "GSP"),
+                        "6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q",
+                    ],
+                )],
+            )"#,
+        )
+        .unwrap();
+        let info = &catalog.courses[0];
+        assert_eq!(info.play_style(), PlayStyle::Doubles);
+        assert_eq!(
+            info.songs[3],
+            "6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q"
+                .parse::<SongId>()
+                .unwrap()
+                .into()
+        );
+
+        let song = test_util::song(
+            "6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q",
+            "song",
+            [3, 7, 12, 15, 17, 7, 12, 16, 17],
+        )
+        .build();
+        let resolved: Vec<_> = info
+            .songs
+            .iter()
+            .map(|entry| entry.resolve(&song, info.play_style()))
+            .collect();
+        // ESP is played as EDP in doubles, and there is no beginner doubles
+        assert_eq!(
+            resolved,
+            [Some((Chart::EDP, 16)), Some((Chart::CDP, 17)), None, None]
+        );
+
+        let json = r#"[{"name": "FUTURE", "play_style": "Singles",
+            "songs": [{"song": "6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q", "chart": "challenge"}]}]"#;
+        let catalog = CourseCatalog::from_json(json).unwrap();
+        let info = &catalog.courses[0];
+        assert_eq!(
+            info.songs[0].resolve(&song, info.play_style()),
+            Some((Chart::CSP, 17))
+        );
+
+        let course = Course {
+            name: "course".to_string(),
+            search_names: vec![],
+            songs: vec![
+                Some(CourseSong {
+                    song: song.clone(),
+                    bpm: None,
+                    chart: Some((Chart::ESP, 15)),
+                }),
+                Some(CourseSong {
+                    song,
+                    bpm: None,
+                    chart: None,
+                }),
+                None,
+            ],
+            play_style: PlayStyle::Singles,
+            category: CourseCategory::CourseTrial,
+            dan_level: None,
+        };
+        assert_eq!(course.levels(), [Some(15), None, None]);
+        assert_eq!(course.chart_summary(), "ESP 15, ???, ???");
+    }
+
+    #[test]
+    fn load_bundled_catalogs() {
+        let catalog = CourseCatalog::from_ron(include_str!("../courses.ron")).unwrap();
+        for dan in [CourseCategory::DanSingles, CourseCategory::DanDoubles] {
+            let levels: Vec<_> = catalog.category(dan).map(|c| c.dan_level()).collect();
+            let mut expected: Vec<_> = (1..=10).map(|d| Some(DanLevel::Dan(d))).collect();
+            expected.push(Some(DanLevel::Kaiden));
+            assert_eq!(levels, expected);
+        }
+        let trials: Vec<_> = catalog.category(CourseCategory::CourseTrial).collect();
+        assert!(!trials.is_empty());
+        assert!(trials.iter().all(|c| c.dan_level().is_none()));
+
+        let json = CourseCatalog::from_json(include_str!("../courses.json")).unwrap();
+        assert!(json
+            .courses
+            .iter()
+            .all(|c| c.category == CourseCategory::CourseTrial && c.songs.len() == 4));
+
+        let unknown = catalog.unknown_songs(&[]);
+        let num_songs: usize = catalog.courses.iter().map(|c| c.songs.len()).sum();
+        assert_eq!(unknown.len(), num_songs);
+    }
+
+    #[test]
+    fn course_bpms() {
+        let info = CourseSerializeInfo {
+            name: "EXCITING WINTER".to_string(),
+            search_names: vec![],
+            songs: [
+                "6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q",
+                "0bq9qI9PoPIlQl89bDO60o9q8I1iIP66",
+            ]
+            .map(|id| id.parse::<SongId>().unwrap().into())
+            .into(),
+            play_style: None,
+            category: CourseCategory::CourseTrial,
+        };
+        let cached = test_util::song("6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q", "song", [0; 9])
+            .bpm(Bpm::Constant(150))
+            .build();
+        let uncached = test_util::song("0bq9qI9PoPIlQl89bDO60o9q8I1iIP66", "song", [0; 9]).build();
+        let songs = [cached, uncached];
+
+        // Songs whose BPM couldn't be fetched just don't have one
+        let course = Course::from_song_list(info.clone(), &songs, &HashMap::new());
+        let bpms: Vec<_> = course
+            .songs
+            .iter()
+            .map(|s| s.as_ref().unwrap().bpm)
+            .collect();
+        assert_eq!(bpms, [Some(Bpm::Constant(150)), None]);
+
+        let fetched = HashMap::from([(songs[1].song_id.clone(), Bpm::Constant(200))]);
+        let course = Course::from_song_list(info, &songs, &fetched);
+        let bpms: Vec<_> = course
+            .songs
+            .iter()
+            .map(|s| s.as_ref().unwrap().bpm)
+            .collect();
+        assert_eq!(bpms, [Some(Bpm::Constant(150)), Some(Bpm::Constant(200))]);
     }
 }
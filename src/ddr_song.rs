@@ -1,15 +1,31 @@
 use std::collections::HashMap;
+use std::fmt;
 
 use once_cell::sync::Lazy;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use tracing::{info, warn};
 
-use crate::website_backends::sanbai::{DDRVersion, Difficulties, LockTypes, SanbaiSong};
+use crate::search::normalize::{name_search_forms, normalize_search_text};
+use crate::website_backends::sanbai::song_details::{get_sanbai_song_details, SongDetails};
+use crate::website_backends::sanbai::{
+    jacket_url, DDRVersion, Difficulties, JacketSize, LockTypes, SanbaiSong,
+};
 use crate::website_backends::skill_attack::{SkillAttackIndex, SkillAttackSong};
 use crate::{HttpClient, Result};
 
+mod chart;
+pub use chart::{Chart, ChartClass, ChartParseError, PlayStyle};
+/// Detecting changes between song lists, like new songs and rerates
+pub mod diff;
+/// Normalization of song names between sources
+pub mod normalize;
 mod song_id;
+/// Decoding of sanbai lock types into unlock conditions
+pub mod unlock;
+use normalize::{MismatchReport, SongMismatch};
 pub use song_id::SongId;
+use unlock::{LockTypeTable, UnlockCondition};
 
 #[derive(Debug, Clone)]
 pub struct DDRSong {
@@ -17,13 +33,27 @@ pub struct DDRSong {
     pub skill_attack_index: Option<SkillAttackIndex>,
     pub song_name: String,
     pub romanized_name: Option<String>,
-    /// A list of all variations of the song name, all lowercase
+    /// A list of all variations of the song name, normalized for searching with
+    /// [`name_search_forms`] so kana names also have a romaji variation
     pub search_names: Vec<String>,
+    /// From Skill Attack, or from the sanbai song details if Skill Attack doesn't have the song
+    pub artist: Option<String>,
+    /// The artist name and each individual artist in it, normalized for
+    /// searching with [`normalize_search_text`]
+    pub artist_search_names: Vec<String>,
+    /// The initial the song is sorted under in game, '#' for numbers and symbols
+    pub title_initial: char,
     pub version_num: DDRVersion,
     pub deleted: bool,
     pub ratings: Difficulties,
     // Lock condition, i.e. Extra Savior, Golden League, Unlock Event, etc.
     pub lock_types: Option<LockTypes>,
+    /// `lock_types` decoded, indexed by [`Chart`]. Every code is
+    /// [`UnlockCondition::Unknown`] until [`DDRSong::decode_lock_types`] is
+    /// called, which the database does for every song it gets
+    pub unlock_conditions: [UnlockCondition; 9],
+    /// `None` if the BPM hasn't been fetched yet, or if sanbai doesn't know it
+    pub bpm: Option<Bpm>,
 }
 
 impl DDRSong {
@@ -35,18 +65,37 @@ impl DDRSong {
             .chain(sanbai.romanized_name.as_deref())
             .chain(sanbai.alternate_name.iter().flat_map(|s| s.split('/')))
             .chain(sanbai.searchable_name.iter().flat_map(|s| s.split('/')))
-            .map(|s| s.to_lowercase())
-            .collect();
+            .flat_map(name_search_forms)
+            .fold(vec![], |mut names, name| {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+                names
+            });
+        let artist = skill_attack.map(|s| s.artist_name.clone());
+        let artist_search_names = artist
+            .as_deref()
+            .map(artist_search_names)
+            .unwrap_or_default();
         Self {
             song_id: sanbai.song_id.clone(),
             skill_attack_index: skill_attack.map(|s| s.skill_attack_index),
             song_name: sanbai.song_name.clone(),
             romanized_name: sanbai.romanized_name.clone(),
             search_names,
+            artist,
+            artist_search_names,
+            title_initial: sanbai
+                .alphabet
+                .unwrap_or_else(|| title_initial(&sanbai.song_name)),
             version_num: sanbai.version_num,
             deleted: sanbai.deleted,
             ratings: sanbai.ratings,
             lock_types: sanbai.lock_types,
+            // Decoded by `decode_lock_types` with the table of the database,
+            // until then every lock code is unknown
+            unlock_conditions: LockTypeTable::empty().decode_all(sanbai.lock_types.as_ref()),
+            bpm: None,
         }
     }
 
@@ -54,13 +103,23 @@ impl DDRSong {
         sanbai_songs: &[SanbaiSong],
         skill_attack_songs: &[SkillAttackSong],
     ) -> Vec<Self> {
+        Self::from_combining_song_lists_with_report(sanbai_songs, skill_attack_songs).0
+    }
+
+    /// Combines the song lists like [`DDRSong::from_combining_song_lists`], also
+    /// reporting every song whose name or difficulties disagree between the two
+    pub fn from_combining_song_lists_with_report(
+        sanbai_songs: &[SanbaiSong],
+        skill_attack_songs: &[SkillAttackSong],
+    ) -> (Vec<Self>, MismatchReport) {
         info!("Combining sanbai and skill attack song lists");
+        let mut report = MismatchReport::default();
         let mut ddr_song_map: HashMap<SongId, Self> = sanbai_songs
             .iter()
             .map(|s| {
                 (
                     s.song_id.clone(),
-                    Self::new_from_sanbai_and_skillattack(&s, None),
+                    Self::new_from_sanbai_and_skillattack(s, None),
                 )
             })
             .collect();
@@ -74,79 +133,111 @@ impl DDRSong {
             // on top of its song list. Sanbai also usually has more information about the
             // song so we consider it more valuable than only having skill attack info
             if let Some(ddr_song) = ddr_song_map.get_mut(&sa_song.song_id) {
-                // TODO sanity check on difficulties? If only to emit a warning in the logs
-                // TODO sanity check on song name? We already know that Sanbai changed some of
-                // the names slightly at first in attempt to make searching easier, like
-                // by changing some full width characters to half width, some smart quotes, etc.
+                // We already know that Sanbai changed some of the names slightly at first
+                // in attempt to make searching easier, like by changing some full width
+                // characters to half width, some smart quotes, etc. so we compare the
+                // normalized names
+                if let Some(mismatch) = SongMismatch::between(ddr_song, sa_song) {
+                    warn!(
+                        "Sanbai and Skill Attack disagree on {} ({}): {:?}",
+                        ddr_song.song_name, ddr_song.song_id, mismatch
+                    );
+                    report.mismatches.push(mismatch);
+                }
                 ddr_song.skill_attack_index = Some(sa_song.skill_attack_index);
+                ddr_song.set_artist(sa_song.artist_name.clone());
             }
         }
 
         let mut out: Vec<_> = ddr_song_map.into_values().collect();
         // Sort for consistency
         out.sort_by(|a, b| a.song_name.cmp(&b.song_name));
-        info!("Combining complete");
-        out
+        report
+            .mismatches
+            .sort_by(|a, b| a.sanbai_name.cmp(&b.sanbai_name));
+        info!(
+            "Combining complete, {} songs disagree between sources",
+            report.len()
+        );
+        (out, report)
     }
 
-    pub async fn fetch_bpm(&self, http: HttpClient) -> Result<Option<Bpm>> {
-        // Matches strings like this
-        // "<span class="sp-bpm">75-528</span>"
-        //              ^--------++-+++------^
-        //                       ^^ ^^^
-        //                       |     \
-        //                       first  second
-        // "<span class="sp-bpm">150</span>"
-        //              ^--------+++------^
-        //                       ^^^
-        //                       |
-        //                       first
-        static SP_BPM_FINDER: Lazy<Regex> = Lazy::new(|| {
-            Regex::new(r#""sp-bpm">(?P<first>\d+)(-(?P<second>\d+))?</span>"#).unwrap()
-        });
-
-        let song_info_url = format!("https://3icecream.com/ddr/song_details/{}", self.song_id);
-
-        let response = http.get(song_info_url).send().await?.text().await?;
-        let mut cap_iter = SP_BPM_FINDER.captures_iter(&response);
-        if let Some(cap) = cap_iter.next() {
-            match (cap.name("first"), cap.name("second")) {
-                (Some(first_cap), Some(second_cap)) => {
-                    let lower = first_cap.as_str().parse::<u16>().expect("Really big bpm");
-                    let upper = second_cap.as_str().parse::<u16>().expect("Really big bpm");
-                    if let Some(main_bpm_cap) = cap_iter.next() {
-                        let main = main_bpm_cap
-                            .name("first")
-                            .expect("This should be impossible")
-                            .as_str()
-                            .parse::<u16>()
-                            .expect("Really big bpm");
-                        Ok(Some(Bpm::Range { lower, upper, main }))
-                    } else {
-                        warn!("We couldn't find the main bpm!");
-                        Err(crate::error::Error::SanbaiBpmHtmlParseError)
-                    }
-                }
-                (Some(first_cap), None) => {
-                    let bpm = first_cap.as_str().parse::<u16>().expect("Really big bpm");
-                    Ok(Some(Bpm::Constant(bpm)))
-                }
-                _ => unreachable!("This case should be impossible"),
-            }
-        } else {
-            // Sanity check, we should see a `"sp-missing-bpm"` in the html
-            // if not something may have changed with the html so we should give an error for that
-            if response.contains(r#""sp-missing-bpm""#) {
-                Ok(None)
-            } else {
-                warn!("Bpm html might have changed!");
-                Err(crate::error::Error::SanbaiBpmHtmlParseError)
+    /// How the chart is unlocked in game
+    pub fn unlock_condition(&self, chart: Chart) -> UnlockCondition {
+        self.unlock_conditions[chart as usize]
+    }
+
+    /// Decodes `lock_types` again with an updated table
+    pub fn decode_lock_types(&mut self, table: &LockTypeTable) {
+        self.unlock_conditions = table.decode_all(self.lock_types.as_ref());
+    }
+
+    /// Sets the artist and regenerates the artist search names
+    pub fn set_artist(&mut self, artist: String) {
+        self.artist_search_names = artist_search_names(&artist);
+        self.artist = Some(artist);
+    }
+
+    /// Fills in the BPM, and the artist if we don't already have one, from
+    /// the sanbai song details
+    pub fn apply_song_details(&mut self, details: &SongDetails) {
+        self.bpm = details.bpm;
+        if self.artist.is_none() {
+            if let Some(artist) = &details.artist {
+                self.set_artist(artist.clone());
             }
         }
     }
+
+    /// 256x256 jacket
+    pub fn get_jacket_url(&self) -> String {
+        jacket_url(&self.song_id, JacketSize::Large)
+    }
+
+    /// 120x120 jacket
+    pub fn get_small_jacket_url(&self) -> String {
+        jacket_url(&self.song_id, JacketSize::Small)
+    }
+
+    /// Fetches the sanbai song details page of this song
+    pub async fn fetch_song_details(&self, http: HttpClient) -> Result<SongDetails> {
+        get_sanbai_song_details(http, &self.song_id).await
+    }
+
+    /// Fetches the BPM of this song from sanbai. `None` if sanbai doesn't know it
+    pub async fn fetch_bpm(&self, http: HttpClient) -> Result<Option<Bpm>> {
+        Ok(self.fetch_song_details(http).await?.bpm)
+    }
+}
+
+/// The initial a song title is sorted under when sanbai doesn't tell us,
+/// the first letter uppercased or '#' for anything else
+fn title_initial(song_name: &str) -> char {
+    match song_name.trim_start().chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => c.to_ascii_uppercase(),
+        _ => '#',
+    }
+}
+
+/// The full artist name, followed by each individual artist for
+/// collaborations like "NAOKI feat. YASPY" or "L.E.D. vs GUHROOVY"
+fn artist_search_names(artist: &str) -> Vec<String> {
+    static ARTIST_SEPARATOR: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"\s+(?:feat\.?|ft\.?|vs\.?|with|&|×|x)\s+|\s*/\s*|\s*、\s*").unwrap()
+    });
+
+    let artist = normalize_search_text(artist.trim());
+    let mut names = vec![];
+    for part in ARTIST_SEPARATOR.split(&artist).map(str::trim) {
+        if !part.is_empty() && part != artist && !names.iter().any(|n| n == part) {
+            names.push(part.to_string());
+        }
+    }
+    names.insert(0, artist);
+    names
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Bpm {
     Constant(u16),
     Range { lower: u16, upper: u16, main: u16 },
@@ -161,89 +252,41 @@ impl Bpm {
     }
 }
 
-#[repr(u8)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub enum Chart {
-    GSP,
-    BSP,
-    DSP,
-    ESP,
-    CSP,
-    BDP,
-    DDP,
-    EDP,
-    CDP,
-}
-
-impl Chart {
-    pub fn is_challenge(&self) -> bool {
-        matches!(self, Chart::CSP)
-    }
-
-    pub fn is_doubles(&self) -> bool {
-        *self as u8 > 4
-    }
-
-    pub fn from_index(index: usize) -> Option<Self> {
-        Some(match index {
-            0 => Self::GSP,
-            1 => Self::BSP,
-            2 => Self::DSP,
-            3 => Self::ESP,
-            4 => Self::CSP,
-            5 => Self::BDP,
-            6 => Self::DDP,
-            7 => Self::EDP,
-            8 => Self::CDP,
-            _ => return None,
-        })
+impl fmt::Display for Bpm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Bpm::Constant(bpm) => write!(f, "{}", bpm),
+            Bpm::Range { lower, upper, main } => write!(f, "{}-{} ({})", lower, upper, main),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Chart;
+    use super::{artist_search_names, title_initial};
+    #[test]
+    fn title_initials() {
+        assert_eq!(title_initial("PARANOiA"), 'P');
+        assert_eq!(title_initial("bag"), 'B');
+        assert_eq!(title_initial("12345"), '#');
+        assert_eq!(title_initial("☆shining☆"), '#');
+        assert_eq!(title_initial(""), '#');
+    }
+
     #[test]
-    fn chart_is_doubles() {
-        assert!(!Chart::GSP.is_doubles());
-        assert!(!Chart::BSP.is_doubles());
-        assert!(!Chart::DSP.is_doubles());
-        assert!(!Chart::ESP.is_doubles());
-        assert!(!Chart::CSP.is_doubles());
-        assert!(Chart::BDP.is_doubles());
-        assert!(Chart::DDP.is_doubles());
-        assert!(Chart::EDP.is_doubles());
-        assert!(Chart::CDP.is_doubles());
+    fn artist_names() {
+        assert_eq!(artist_search_names("NAOKI"), ["naoki"]);
+        assert_eq!(
+            artist_search_names("NAOKI feat. YASPY"),
+            ["naoki feat. yaspy", "naoki", "yaspy"]
+        );
+        assert_eq!(
+            artist_search_names("L.E.D. vs GUHROOVY"),
+            ["l.e.d. vs guhroovy", "l.e.d.", "guhroovy"]
+        );
+        assert_eq!(
+            artist_search_names("TAG / NAOKI & 2MB"),
+            ["tag / naoki & 2mb", "tag", "naoki", "2mb"]
+        );
     }
 }
-// Differences between Sanbai and Skill Attack/EAmuse site
-// - Space between song name and parenteticals `Possession(EDP Mix)`
-// - sometimes SA has full width parenthesis, `!`, `+`
-// - a couple of smart quotes (over the "period", dreamin')
-// - Qipchāq and Qipchãq
-// - … and ...
-// /// Normalize a song name so that slight irregularties in how the name was spelt are ignored
-// /// when compared
-// fn normalize_name(input: &str) -> String {
-//     input
-//         .chars()
-//         .filter(|c| !c.is_whitespace())
-//         .map(|c| match c {
-//             '！' => '!',
-//             '（' => '(',
-//             '）' => ')',
-//             '“' | '”' => '"',
-//             'ã' | 'ā' => 'a',
-//             '＋' => '+',
-//             '’' => '\'',
-//             _ => c,
-//         })
-//         .flat_map(|c| {
-//             if c == '…' {
-//                 std::iter::repeat('.').take(3)
-//             } else {
-//                 std::iter::repeat(c).take(1)
-//             }
-//         })
-//         .collect()
-// }
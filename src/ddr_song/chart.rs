@@ -0,0 +1,358 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Singles (SP) or doubles (DP)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PlayStyle {
+    Singles,
+    Doubles,
+}
+
+/// The difficulty class of a chart, shared between singles and doubles
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ChartClass {
+    Beginner,
+    Basic,
+    Difficult,
+    Expert,
+    Challenge,
+}
+
+impl ChartClass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChartClass::Beginner => "Beginner",
+            ChartClass::Basic => "Basic",
+            ChartClass::Difficult => "Difficult",
+            ChartClass::Expert => "Expert",
+            ChartClass::Challenge => "Challenge",
+        }
+    }
+
+    /// The colour the game uses for this difficulty, as `0xRRGGBB`
+    pub fn colour(&self) -> u32 {
+        match self {
+            ChartClass::Beginner => 0x66ccff,
+            ChartClass::Basic => 0xffcc33,
+            ChartClass::Difficult => 0xff3366,
+            ChartClass::Expert => 0x33cc66,
+            ChartClass::Challenge => 0xcc66ff,
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Chart {
+    GSP,
+    BSP,
+    DSP,
+    ESP,
+    CSP,
+    BDP,
+    DDP,
+    EDP,
+    CDP,
+}
+
+impl Chart {
+    /// Every chart, in the same order as their indices
+    pub const ALL: [Chart; 9] = [
+        Chart::GSP,
+        Chart::BSP,
+        Chart::DSP,
+        Chart::ESP,
+        Chart::CSP,
+        Chart::BDP,
+        Chart::DDP,
+        Chart::EDP,
+        Chart::CDP,
+    ];
+
+    /// Every chart, in the same order as their indices
+    pub fn iter() -> impl Iterator<Item = Chart> {
+        Self::ALL.into_iter()
+    }
+
+    /// Parses only short names like "esp" or "CDP", ignoring case, with
+    /// `"bSP"` as beginner singles like [`Chart::from_str`]
+    pub fn from_short_name(s: &str) -> Option<Self> {
+        if s == "bSP" {
+            return Some(Chart::GSP);
+        }
+        Self::ALL
+            .into_iter()
+            .find(|c| c.short_name().eq_ignore_ascii_case(s))
+    }
+
+    /// `true` for challenge singles only. Use [`Chart::class`] to include
+    /// challenge doubles
+    pub fn is_challenge(&self) -> bool {
+        matches!(self, Chart::CSP)
+    }
+
+    pub fn is_doubles(&self) -> bool {
+        *self as u8 > 4
+    }
+
+    pub fn play_style(&self) -> PlayStyle {
+        if self.is_doubles() {
+            PlayStyle::Doubles
+        } else {
+            PlayStyle::Singles
+        }
+    }
+
+    pub fn class(&self) -> ChartClass {
+        match self {
+            Chart::GSP => ChartClass::Beginner,
+            Chart::BSP | Chart::BDP => ChartClass::Basic,
+            Chart::DSP | Chart::DDP => ChartClass::Difficult,
+            Chart::ESP | Chart::EDP => ChartClass::Expert,
+            Chart::CSP | Chart::CDP => ChartClass::Challenge,
+        }
+    }
+
+    /// The colour the game uses for this chart, as `0xRRGGBB`
+    pub fn colour(&self) -> u32 {
+        self.class().colour()
+    }
+
+    /// The chart with this class and play style. There is no beginner doubles chart
+    pub fn from_class(class: ChartClass, play_style: PlayStyle) -> Option<Self> {
+        Some(match (class, play_style) {
+            (ChartClass::Beginner, PlayStyle::Singles) => Chart::GSP,
+            (ChartClass::Basic, PlayStyle::Singles) => Chart::BSP,
+            (ChartClass::Difficult, PlayStyle::Singles) => Chart::DSP,
+            (ChartClass::Expert, PlayStyle::Singles) => Chart::ESP,
+            (ChartClass::Challenge, PlayStyle::Singles) => Chart::CSP,
+            (ChartClass::Beginner, PlayStyle::Doubles) => return None,
+            (ChartClass::Basic, PlayStyle::Doubles) => Chart::BDP,
+            (ChartClass::Difficult, PlayStyle::Doubles) => Chart::DDP,
+            (ChartClass::Expert, PlayStyle::Doubles) => Chart::EDP,
+            (ChartClass::Challenge, PlayStyle::Doubles) => Chart::CDP,
+        })
+    }
+
+    /// The doubles chart of the same class. Beginner has no doubles chart
+    pub fn to_doubles(&self) -> Option<Self> {
+        Self::from_class(self.class(), PlayStyle::Doubles)
+    }
+
+    /// The singles chart of the same class
+    pub fn to_singles(&self) -> Self {
+        Self::from_class(self.class(), PlayStyle::Singles).expect("every class has a singles chart")
+    }
+
+    /// The chart of the same class in the other play style
+    pub fn counterpart(&self) -> Option<Self> {
+        match self.play_style() {
+            PlayStyle::Singles => self.to_doubles(),
+            PlayStyle::Doubles => Some(self.to_singles()),
+        }
+    }
+
+    /// e.g. "ESP"
+    pub fn short_name(&self) -> &'static str {
+        match self {
+            Chart::GSP => "GSP",
+            Chart::BSP => "BSP",
+            Chart::DSP => "DSP",
+            Chart::ESP => "ESP",
+            Chart::CSP => "CSP",
+            Chart::BDP => "BDP",
+            Chart::DDP => "DDP",
+            Chart::EDP => "EDP",
+            Chart::CDP => "CDP",
+        }
+    }
+
+    /// e.g. "Expert Singles"
+    pub fn long_name(&self) -> String {
+        let style = match self.play_style() {
+            PlayStyle::Singles => "Singles",
+            PlayStyle::Doubles => "Doubles",
+        };
+        format!("{} {}", self.class().name(), style)
+    }
+
+    pub fn from_index(index: usize) -> Option<Self> {
+        Self::ALL.get(index).copied()
+    }
+}
+
+impl fmt::Display for Chart {
+    /// Writes the short name like "ESP", or the long name like
+    /// "Expert Singles" with the alternate flag (`{:#}`)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            write!(f, "{}", self.long_name())
+        } else {
+            write!(f, "{}", self.short_name())
+        }
+    }
+}
+
+/// Serialized as the short name like "ESP"
+impl Serialize for Chart {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.short_name())
+    }
+}
+
+/// Deserialized from anything [`Chart::from_str`] accepts
+impl<'de> Deserialize<'de> for Chart {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ChartParseError {
+    #[error("`{0}` isn't a chart")]
+    UnknownChart(String),
+    #[error("There is no beginner doubles chart")]
+    BeginnerDoubles,
+}
+
+impl FromStr for Chart {
+    type Err = ChartParseError;
+
+    /// Parses short names like "esp" or "CDP" and long names like "expert" or
+    /// "challenge doubles", ignoring case. Long names without a play style
+    /// are singles.
+    ///
+    /// `"bSP"` is beginner singles, as sanbai writes it, while any other
+    /// casing of "bsp" is basic singles
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(chart) = Self::from_short_name(s) {
+            return Ok(chart);
+        }
+        let unknown = || ChartParseError::UnknownChart(s.to_string());
+
+        let lower = s.to_lowercase();
+
+        let mut class = None;
+        let mut play_style = None;
+        for word in lower
+            .split(|c: char| c.is_whitespace() || c == '-' || c == '_')
+            .filter(|w| !w.is_empty())
+        {
+            let (new_class, new_style) = match word {
+                "beginner" | "beg" => (Some(ChartClass::Beginner), None),
+                "basic" | "bas" | "light" => (Some(ChartClass::Basic), None),
+                "difficult" | "diff" | "dif" | "standard" | "another" => {
+                    (Some(ChartClass::Difficult), None)
+                }
+                "expert" | "exp" | "heavy" | "maniac" => (Some(ChartClass::Expert), None),
+                "challenge" | "chal" | "oni" => (Some(ChartClass::Challenge), None),
+                "singles" | "single" | "sp" => (None, Some(PlayStyle::Singles)),
+                "doubles" | "double" | "dp" => (None, Some(PlayStyle::Doubles)),
+                _ => return Err(unknown()),
+            };
+            // Only one of each is allowed
+            if (new_class.is_some() && class.is_some())
+                || (new_style.is_some() && play_style.is_some())
+            {
+                return Err(unknown());
+            }
+            class = class.or(new_class);
+            play_style = play_style.or(new_style);
+        }
+
+        let class = class.ok_or_else(unknown)?;
+        Self::from_class(class, play_style.unwrap_or(PlayStyle::Singles))
+            .ok_or(ChartParseError::BeginnerDoubles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chart_is_doubles() {
+        assert!(!Chart::GSP.is_doubles());
+        assert!(!Chart::BSP.is_doubles());
+        assert!(!Chart::DSP.is_doubles());
+        assert!(!Chart::ESP.is_doubles());
+        assert!(!Chart::CSP.is_doubles());
+        assert!(Chart::BDP.is_doubles());
+        assert!(Chart::DDP.is_doubles());
+        assert!(Chart::EDP.is_doubles());
+        assert!(Chart::CDP.is_doubles());
+    }
+
+    #[test]
+    fn chart_serde() {
+        for chart in Chart::iter() {
+            let json = serde_json::to_string(&chart).unwrap();
+            assert_eq!(json, format!("\"{}\"", chart.short_name()));
+            assert_eq!(serde_json::from_str::<Chart>(&json).unwrap(), chart);
+        }
+        assert_eq!(
+            serde_json::from_str::<Chart>("\"expert doubles\"").unwrap(),
+            Chart::EDP
+        );
+        assert!(serde_json::from_str::<Chart>("\"hard\"").is_err());
+    }
+
+    #[test]
+    fn parse_charts() {
+        let cases = [
+            ("esp", Chart::ESP),
+            ("EsP", Chart::ESP),
+            ("bSP", Chart::GSP),
+            ("BSP", Chart::BSP),
+            ("bsp", Chart::BSP),
+            ("gsp", Chart::GSP),
+            ("expert", Chart::ESP),
+            ("Expert Doubles", Chart::EDP),
+            ("challenge doubles", Chart::CDP),
+            ("doubles challenge", Chart::CDP),
+            ("challenge-dp", Chart::CDP),
+            ("beginner", Chart::GSP),
+            ("heavy", Chart::ESP),
+            ("basic sp", Chart::BSP),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(input.parse(), Ok(expected), "{}", input);
+        }
+        assert_eq!(
+            "beginner doubles".parse::<Chart>(),
+            Err(ChartParseError::BeginnerDoubles)
+        );
+        for input in ["", "doubles", "expert challenge", "esp2", "15"] {
+            assert!(input.parse::<Chart>().is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn display_and_round_trip() {
+        for chart in Chart::iter() {
+            assert_eq!(chart.to_string().parse(), Ok(chart));
+            assert_eq!(format!("{:#}", chart).parse(), Ok(chart));
+            assert_eq!(Chart::from_index(chart as usize), Some(chart));
+        }
+        assert_eq!(format!("{:#}", Chart::CDP), "Challenge Doubles");
+        assert_eq!(format!("{}", Chart::CDP), "CDP");
+    }
+
+    #[test]
+    fn counterparts() {
+        assert_eq!(Chart::ESP.counterpart(), Some(Chart::EDP));
+        assert_eq!(Chart::EDP.counterpart(), Some(Chart::ESP));
+        assert_eq!(Chart::GSP.counterpart(), None);
+        assert_eq!(Chart::CDP.to_singles(), Chart::CSP);
+        assert!(Chart::CSP.is_challenge());
+        assert!(!Chart::CDP.is_challenge());
+        assert_eq!(Chart::CDP.class(), ChartClass::Challenge);
+        assert_eq!(Chart::DDP.class(), ChartClass::Difficult);
+        assert_eq!(Chart::BSP.colour(), Chart::BDP.colour());
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ddr_song::{Chart, DDRSong, SongId};
+use crate::scores::{Player, ScoreRow};
+use crate::website_backends::sanbai::LockTypes;
+
+/// The differences between an old and a new song list, keyed by [`SongId`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SongListDiff {
+    /// Songs that weren't in the old list
+    pub added: Vec<SongId>,
+    /// Songs that are still listed, but are now marked as deleted
+    pub newly_deleted: Vec<SongId>,
+    /// Songs that were marked as deleted, but no longer are
+    pub revived: Vec<SongId>,
+    /// Songs that aren't in the new list at all
+    pub removed: Vec<SongId>,
+    /// Charts whose level changed, were added or were removed
+    pub level_changes: Vec<LevelChange>,
+    /// Songs whose lock conditions changed
+    pub lock_changes: Vec<LockChange>,
+}
+
+/// A change to the level of a chart. `None` means the song doesn't have that chart
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LevelChange {
+    pub song_id: SongId,
+    pub chart: Chart,
+    pub old_level: Option<u8>,
+    pub new_level: Option<u8>,
+}
+
+impl LevelChange {
+    /// `true` if the chart existed before and after, and only its level changed
+    pub fn is_rerate(&self) -> bool {
+        self.old_level.is_some() && self.new_level.is_some()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockChange {
+    pub song_id: SongId,
+    pub old: Option<LockTypes>,
+    pub new: Option<LockTypes>,
+}
+
+/// A score of a player on a chart that was rerated, so it is now in a
+/// different level folder
+#[derive(Debug, Clone, PartialEq)]
+pub struct MovedScore {
+    pub song_id: SongId,
+    pub chart: Chart,
+    pub old_level: u8,
+    pub new_level: u8,
+    pub score: ScoreRow,
+}
+
+impl SongListDiff {
+    pub fn new(old: &[DDRSong], new: &[DDRSong]) -> Self {
+        let old_songs: HashMap<&SongId, &DDRSong> = old.iter().map(|s| (&s.song_id, s)).collect();
+        let mut diff = Self::default();
+
+        for new_song in new {
+            let old_song = match old_songs.get(&new_song.song_id) {
+                Some(old_song) => old_song,
+                None => {
+                    diff.added.push(new_song.song_id.clone());
+                    continue;
+                }
+            };
+
+            match (old_song.deleted, new_song.deleted) {
+                (false, true) => diff.newly_deleted.push(new_song.song_id.clone()),
+                (true, false) => diff.revived.push(new_song.song_id.clone()),
+                _ => {}
+            }
+
+            for (i, (&old_level, &new_level)) in old_song
+                .ratings
+                .0
+                .iter()
+                .zip(&new_song.ratings.0)
+                .enumerate()
+            {
+                if old_level == new_level {
+                    continue;
+                }
+                if let Some(chart) = Chart::from_index(i) {
+                    diff.level_changes.push(LevelChange {
+                        song_id: new_song.song_id.clone(),
+                        chart,
+                        old_level: Some(old_level).filter(|&l| l > 0),
+                        new_level: Some(new_level).filter(|&l| l > 0),
+                    });
+                }
+            }
+
+            if old_song.lock_types != new_song.lock_types {
+                diff.lock_changes.push(LockChange {
+                    song_id: new_song.song_id.clone(),
+                    old: old_song.lock_types,
+                    new: new_song.lock_types,
+                });
+            }
+        }
+
+        let new_ids: HashSet<&SongId> = new.iter().map(|s| &s.song_id).collect();
+        diff.removed = old
+            .iter()
+            .filter(|s| !new_ids.contains(&s.song_id))
+            .map(|s| s.song_id.clone())
+            .collect();
+
+        diff
+    }
+
+    /// `true` if nothing changed between the song lists
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Only the level changes where the chart existed in both lists
+    pub fn rerates(&self) -> impl Iterator<Item = &LevelChange> {
+        self.level_changes.iter().filter(|c| c.is_rerate())
+    }
+
+    /// Every score of the player on a rerated chart
+    pub fn moved_scores(&self, player: &Player) -> Vec<MovedScore> {
+        self.rerates()
+            .filter_map(|change| {
+                let score = player.scores.get(&change.song_id)?[change.chart as usize]?;
+                Some(MovedScore {
+                    song_id: change.song_id.clone(),
+                    chart: change.chart,
+                    old_level: change.old_level?,
+                    new_level: change.new_level?,
+                    score,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scores::{LampType, Scores};
+    use crate::test_util::song;
+
+    const KEPT: &str = "6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q";
+    const DELETED: &str = "0bq9qI9PoPIlQl89bDO60o9q8I1iIP66";
+    const REMOVED: &str = "ld6P1lbb0bPO9doqbbPOoPb8qoDo8id0";
+    const ADDED: &str = "qOlDPoiqibIOqod69dPilbiqD6qdO1qQ";
+
+    #[test]
+    fn song_list_diff() {
+        let old = [
+            song(KEPT, "song", [3, 7, 12, 15, 0, 7, 12, 15, 0]).build(),
+            song(DELETED, "song", [3, 6, 9, 12, 0, 6, 9, 12, 0]).build(),
+            song(REMOVED, "song", [1, 2, 3, 4, 0, 2, 3, 4, 0])
+                .deleted()
+                .build(),
+        ];
+        let new = [
+            song(KEPT, "song", [3, 7, 12, 16, 17, 7, 12, 15, 0])
+                .lock_types([0, 0, 0, 0, 1, 0, 0, 0, 0])
+                .build(),
+            song(DELETED, "song", [3, 6, 9, 12, 0, 6, 9, 12, 0])
+                .deleted()
+                .build(),
+            song(ADDED, "song", [2, 5, 8, 11, 0, 5, 8, 11, 0]).build(),
+        ];
+        let id = |s: &str| s.parse::<SongId>().unwrap();
+
+        let diff = SongListDiff::new(&old, &new);
+        assert_eq!(diff.added, [id(ADDED)]);
+        assert_eq!(diff.newly_deleted, [id(DELETED)]);
+        assert!(diff.revived.is_empty());
+        assert_eq!(diff.removed, [id(REMOVED)]);
+        assert_eq!(
+            diff.level_changes,
+            [
+                LevelChange {
+                    song_id: id(KEPT),
+                    chart: Chart::ESP,
+                    old_level: Some(15),
+                    new_level: Some(16),
+                },
+                LevelChange {
+                    song_id: id(KEPT),
+                    chart: Chart::CSP,
+                    old_level: None,
+                    new_level: Some(17),
+                },
+            ]
+        );
+        assert_eq!(diff.rerates().count(), 1);
+        assert_eq!(diff.lock_changes.len(), 1);
+        assert_eq!(diff.lock_changes[0].old, None);
+
+        let mut player = Player::new("MARK", 51527130, None::<String>);
+        let score = ScoreRow {
+            score: 990_000,
+            lamp: LampType::PerfectCombo,
+            time_played: None,
+        };
+        let scores = Scores {
+            expert_score: Some(score),
+            diff_score: Some(score),
+            ..Default::default()
+        };
+        player.scores.insert(id(KEPT), scores);
+        assert_eq!(
+            diff.moved_scores(&player),
+            [MovedScore {
+                song_id: id(KEPT),
+                chart: Chart::ESP,
+                old_level: 15,
+                new_level: 16,
+                score,
+            }]
+        );
+
+        assert!(SongListDiff::new(&new, &new).is_empty());
+    }
+}
@@ -0,0 +1,201 @@
+use crate::ddr_song::{Chart, DDRSong, SongId};
+use crate::website_backends::skill_attack::{SkillAttackIndex, SkillAttackSong};
+
+// Differences between Sanbai and Skill Attack/EAmuse site
+// - Space between song name and parenteticals `Possession(EDP Mix)`
+// - sometimes SA has full width parenthesis, `!`, `+`
+// - a couple of smart quotes (over the "period", dreamin')
+// - Qipchāq and Qipchãq
+// - … and ...
+
+/// Normalize a song name so that slight irregularties in how the name was spelt are ignored
+/// when compared
+///
+/// # Examples
+///
+/// ```
+/// use score_websites::ddr_song::normalize::normalize_name;
+///
+/// assert_eq!(normalize_name("Possession (EDP Mix)"), normalize_name("Possession(EDP Mix)"));
+/// assert_eq!(normalize_name("Qipchāq"), normalize_name("Qipchãq"));
+/// assert_eq!(normalize_name("dreamin’"), normalize_name("dreamin'"));
+/// assert_ne!(normalize_name("MAX 300"), normalize_name("MAX 360"));
+/// ```
+pub fn normalize_name(input: &str) -> String {
+    input
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| match c {
+            // Full width versions of ascii characters, like `！`, `（` and `＋`
+            '！'..='～' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+            '“' | '”' => '"',
+            '‘' | '’' => '\'',
+            'ã' | 'ā' => 'a',
+            _ => c,
+        })
+        .flat_map(|c| {
+            if c == '…' {
+                std::iter::repeat('.').take(3)
+            } else {
+                std::iter::repeat(c).take(1)
+            }
+        })
+        .collect()
+}
+
+/// The songs whose name or difficulties disagree between sanbai and Skill
+/// Attack, even after normalization. These usually mean Skill Attack
+/// has mapped a song to the wrong song id, or one of the sites hasn't caught
+/// up with a rerate yet
+#[derive(Debug, Clone, Default)]
+pub struct MismatchReport {
+    pub mismatches: Vec<SongMismatch>,
+}
+
+impl MismatchReport {
+    pub fn is_empty(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.mismatches.len()
+    }
+}
+
+/// A song that sanbai and Skill Attack disagree about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SongMismatch {
+    pub song_id: SongId,
+    pub skill_attack_index: SkillAttackIndex,
+    pub sanbai_name: String,
+    pub skill_attack_name: String,
+    /// `true` if the names are still different after normalization
+    pub name_mismatch: bool,
+    pub difficulty_mismatches: Vec<DifficultyMismatch>,
+}
+
+/// A chart that has a different level on sanbai and Skill Attack.
+/// `None` means that site doesn't have the chart
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DifficultyMismatch {
+    pub chart: Chart,
+    pub sanbai: Option<u8>,
+    pub skill_attack: Option<u8>,
+}
+
+impl SongMismatch {
+    /// Compares a song made from the sanbai song list with the Skill Attack
+    /// song of the same song id. Returns `None` if they agree
+    pub fn between(ddr_song: &DDRSong, skill_attack: &SkillAttackSong) -> Option<Self> {
+        let name_mismatch =
+            normalize_name(&ddr_song.song_name) != normalize_name(&skill_attack.song_name);
+
+        let skill_attack_levels = [
+            skill_attack.gsp,
+            skill_attack.bsp,
+            skill_attack.dsp,
+            skill_attack.esp,
+            skill_attack.csp,
+            skill_attack.bdp,
+            skill_attack.ddp,
+            skill_attack.edp,
+            skill_attack.cdp,
+        ];
+        let difficulty_mismatches: Vec<_> = ddr_song
+            .ratings
+            .0
+            .iter()
+            .map(|&level| Some(level).filter(|&l| l > 0))
+            .zip(skill_attack_levels)
+            .enumerate()
+            .filter(|(_, (sanbai, skill_attack))| sanbai != skill_attack)
+            .filter_map(|(i, (sanbai, skill_attack))| {
+                Some(DifficultyMismatch {
+                    chart: Chart::from_index(i)?,
+                    sanbai,
+                    skill_attack,
+                })
+            })
+            .collect();
+
+        if !name_mismatch && difficulty_mismatches.is_empty() {
+            return None;
+        }
+        Some(Self {
+            song_id: ddr_song.song_id.clone(),
+            skill_attack_index: skill_attack.skill_attack_index,
+            sanbai_name: ddr_song.song_name.clone(),
+            skill_attack_name: skill_attack.song_name.clone(),
+            name_mismatch,
+            difficulty_mismatches,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_known_differences() {
+        let pairs = [
+            ("Possession (EDP Mix)", "Possession(EDP Mix)"),
+            ("Sakura Sunrise!", "Sakura Sunrise！"),
+            ("Din Don Dan(PLUS)", "Din Don Dan（ＰＬＵＳ）"),
+            ("over the “period”", "over the \"period\""),
+            ("dreamin’", "dreamin'"),
+            ("Qipchāq", "Qipchãq"),
+            ("Hopeful…", "Hopeful..."),
+        ];
+        for (a, b) in pairs {
+            assert_eq!(normalize_name(a), normalize_name(b), "{} vs {}", a, b);
+        }
+    }
+
+    #[test]
+    fn mismatch_between_sources() {
+        let sanbai: crate::website_backends::sanbai::SanbaiSong = serde_json::from_str(
+            r#"{"song_id": "ddib8P601q0Oqdb0Pl8oqobq9DD608P1", "song_name": "Healing Vision ~Angelic mix~",
+                "version_num": 6, "ratings": [5, 7, 11, 13, 0, 8, 10, 14, 0]}"#,
+        )
+        .unwrap();
+        let ddr_song = DDRSong::new_from_sanbai_and_skillattack(&sanbai, None);
+        let mut skill_attack = SkillAttackSong {
+            skill_attack_index: 63,
+            song_id: sanbai.song_id.clone(),
+            gsp: Some(5),
+            bsp: Some(7),
+            dsp: Some(11),
+            esp: Some(13),
+            csp: None,
+            bdp: Some(8),
+            ddp: Some(10),
+            edp: Some(14),
+            cdp: None,
+            song_name: "Healing Vision ～Angelic mix～".into(),
+            artist_name: "2MB".into(),
+        };
+        assert_eq!(SongMismatch::between(&ddr_song, &skill_attack), None);
+
+        skill_attack.esp = Some(12);
+        skill_attack.cdp = Some(15);
+        skill_attack.song_name = "Healing Vision".into();
+        let mismatch = SongMismatch::between(&ddr_song, &skill_attack).unwrap();
+        assert!(mismatch.name_mismatch);
+        assert_eq!(
+            mismatch.difficulty_mismatches,
+            [
+                DifficultyMismatch {
+                    chart: Chart::ESP,
+                    sanbai: Some(13),
+                    skill_attack: Some(12),
+                },
+                DifficultyMismatch {
+                    chart: Chart::CDP,
+                    sanbai: None,
+                    skill_attack: Some(15),
+                },
+            ]
+        );
+    }
+}
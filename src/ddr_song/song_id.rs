@@ -69,8 +69,23 @@ impl<'de> Deserialize<'de> for SongId {
     where
         D: serde::Deserializer<'de>,
     {
-        let s = <&str>::deserialize(deserializer)?;
-        s.parse().map_err(serde::de::Error::custom)
+        struct SongIdVisitor;
+
+        // A visitor instead of deserializing a `&str`, so ids can also be read
+        // from owned strings, like when buffered by an untagged enum
+        impl serde::de::Visitor<'_> for SongIdVisitor {
+            type Value = SongId;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "a 32 character song id")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, s: &str) -> Result<SongId, E> {
+                s.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(SongIdVisitor)
     }
 }
 
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::website_backends::sanbai::LockTypes;
+
+/// How a chart is unlocked in game
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum UnlockCondition {
+    /// Available without unlocking anything
+    Default,
+    ExtraSavior,
+    GoldenLeague,
+    UnlockEvent,
+    /// Only playable as an extra stage
+    ExtraExclusive,
+    /// Only playable in courses
+    CourseExclusive,
+    /// A sanbai lock code that isn't in the [`LockTypeTable`] yet
+    Unknown(i32),
+}
+
+impl UnlockCondition {
+    pub fn is_default(&self) -> bool {
+        matches!(self, UnlockCondition::Default)
+    }
+
+    /// `true` for every condition except [`UnlockCondition::Default`], including unknown codes
+    pub fn is_locked(&self) -> bool {
+        !self.is_default()
+    }
+}
+
+/// Maps the lock codes sanbai uses in its song list to [`UnlockCondition`]s.
+///
+/// The default table is the one in `lock_types.ron`. Sanbai adds new codes
+/// whenever Konami starts a new unlock event, so the table can be extended
+/// with [`LockTypeTable::insert`] or loaded from json without waiting for a
+/// new release. Codes missing from the table decode to
+/// [`UnlockCondition::Unknown`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockTypeTable {
+    codes: HashMap<i32, UnlockCondition>,
+}
+
+impl Default for LockTypeTable {
+    fn default() -> Self {
+        static BUNDLED: Lazy<LockTypeTable> = Lazy::new(|| {
+            ron::from_str(include_str!("../../lock_types.ron"))
+                .expect("the bundled lock type table is valid")
+        });
+        BUNDLED.clone()
+    }
+}
+
+impl LockTypeTable {
+    /// A table without any codes, so only songs without lock types decode
+    /// to [`UnlockCondition::Default`]
+    pub fn empty() -> Self {
+        Self {
+            codes: HashMap::new(),
+        }
+    }
+
+    /// Adds or replaces the condition a code decodes to
+    pub fn insert(&mut self, code: i32, condition: UnlockCondition) {
+        self.codes.insert(code, condition);
+    }
+
+    pub fn decode(&self, code: i32) -> UnlockCondition {
+        self.codes
+            .get(&code)
+            .copied()
+            .unwrap_or(UnlockCondition::Unknown(code))
+    }
+
+    /// Decodes the lock code of every chart. Songs without lock types
+    /// have every chart unlocked by default
+    pub fn decode_all(&self, lock_types: Option<&LockTypes>) -> [UnlockCondition; 9] {
+        match lock_types {
+            Some(lock_types) => lock_types.0.map(|code| self.decode(code)),
+            None => [UnlockCondition::Default; 9],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_lock_types() {
+        let table = LockTypeTable::default();
+        assert_eq!(table.decode(0), UnlockCondition::Default);
+        assert_eq!(table.codes.len(), 1);
+        assert_eq!(table.decode(-1), UnlockCondition::Unknown(-1));
+    }
+
+    #[test]
+    fn decode_lock_types() {
+        let mut table = LockTypeTable::default();
+        let lock_types = LockTypes([0, 0, 0, 0, 120, 0, 0, 0, 250]);
+
+        let decoded = table.decode_all(Some(&lock_types));
+        assert!(decoded[..4].iter().all(UnlockCondition::is_default));
+        assert_eq!(decoded[4], UnlockCondition::Unknown(120));
+        assert!(decoded[4].is_locked());
+
+        table.insert(120, UnlockCondition::GoldenLeague);
+        let decoded = table.decode_all(Some(&lock_types));
+        assert_eq!(decoded[4], UnlockCondition::GoldenLeague);
+        assert_eq!(decoded[8], UnlockCondition::Unknown(250));
+
+        assert_eq!(table.decode_all(None), [UnlockCondition::Default; 9]);
+
+        let json = serde_json::to_string(&table).unwrap();
+        let loaded: LockTypeTable = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, table);
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use thiserror::Error;
+
+use crate::ddr_song::{Chart, DDRSong, SongId};
+use crate::filter::ChartFilter;
+
+/// Draws random charts from the song list for tournaments.
+///
+/// Charts are picked from those passing `filter`, skipping `excluded` ones
+/// like the picks of earlier rounds, and never drawing two charts of the
+/// same song at once. The same seed and song list always draw the same charts
+#[derive(Debug, Clone, Default)]
+pub struct ChartDraw {
+    pub filter: ChartFilter,
+    /// How likely each level is to be drawn, relative to the others. Levels
+    /// missing from the map are never drawn. `None` draws every chart with
+    /// the same chance, so levels with more charts come up more often
+    pub level_weights: Option<HashMap<u8, u32>>,
+    pub excluded: HashSet<(SongId, Chart)>,
+}
+
+/// A chart that was drawn
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DrawnChart {
+    pub song_id: SongId,
+    pub song_name: String,
+    pub chart: Chart,
+    pub level: u8,
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DrawError {
+    #[error("Wanted to draw {requested} charts but only {available} are available")]
+    NotEnoughCharts { requested: usize, available: usize },
+    #[error("There is no card {0} in the draw")]
+    NoSuchCard(usize),
+    #[error("Card {0} is already protected")]
+    AlreadyProtected(usize),
+    #[error("Card {0} is already vetoed")]
+    AlreadyVetoed(usize),
+}
+
+impl ChartDraw {
+    /// Excludes the charts from future draws
+    pub fn exclude<'a>(&mut self, charts: impl IntoIterator<Item = &'a DrawnChart>) {
+        self.excluded
+            .extend(charts.into_iter().map(|c| (c.song_id.clone(), c.chart)));
+    }
+
+    /// Draws `count` charts from the songs, in the order they were drawn
+    pub fn draw(
+        &self,
+        songs: &[DDRSong],
+        count: usize,
+        seed: u64,
+    ) -> Result<Vec<DrawnChart>, DrawError> {
+        let mut pool: Vec<(&DDRSong, Chart, u8)> = self
+            .filter
+            .charts(songs)
+            .filter(|(song, chart, _)| !self.excluded.contains(&(song.song_id.clone(), *chart)))
+            .collect();
+        let mut rng = SplitMix64::new(seed);
+        let mut drawn = Vec::with_capacity(count);
+
+        while drawn.len() < count {
+            let index = match self.pick(&pool, &mut rng) {
+                Some(index) => index,
+                None => {
+                    return Err(DrawError::NotEnoughCharts {
+                        requested: count,
+                        available: drawn.len(),
+                    })
+                }
+            };
+            let (song, chart, level) = pool[index];
+            pool.retain(|(s, _, _)| s.song_id != song.song_id);
+            drawn.push(DrawnChart {
+                song_id: song.song_id.clone(),
+                song_name: song.song_name.clone(),
+                chart,
+                level,
+            });
+        }
+        Ok(drawn)
+    }
+
+    /// The index of a random chart in the pool, `None` if nothing can be drawn
+    fn pick(&self, pool: &[(&DDRSong, Chart, u8)], rng: &mut SplitMix64) -> Option<usize> {
+        let weights = match &self.level_weights {
+            None if pool.is_empty() => return None,
+            None => return Some(rng.below(pool.len() as u64) as usize),
+            Some(weights) => weights,
+        };
+
+        // Pick a level by weight first, then a chart of that level, so the
+        // weights don't depend on how many charts each level has
+        let mut levels: BTreeMap<u8, Vec<usize>> = BTreeMap::new();
+        for (i, &(_, _, level)) in pool.iter().enumerate() {
+            if weights.get(&level).copied().unwrap_or(0) > 0 {
+                levels.entry(level).or_default().push(i);
+            }
+        }
+        let total: u64 = levels.keys().map(|level| weights[level] as u64).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut target = rng.below(total);
+        for (level, charts) in &levels {
+            let weight = weights[level] as u64;
+            if target < weight {
+                return Some(charts[rng.below(charts.len() as u64) as usize]);
+            }
+            target -= weight;
+        }
+        unreachable!("target is below the total weight")
+    }
+}
+
+/// What has happened to a card during a match
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CardState {
+    Open,
+    /// Protected by the player, so it can't be vetoed
+    Protected {
+        player: usize,
+    },
+    Vetoed {
+        player: usize,
+    },
+}
+
+/// The protects and vetoes of a match, over charts drawn with [`ChartDraw`].
+/// Players are numbered however the tournament likes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DrawnMatch {
+    cards: Vec<(DrawnChart, CardState)>,
+}
+
+impl DrawnMatch {
+    pub fn new(charts: Vec<DrawnChart>) -> Self {
+        Self {
+            cards: charts
+                .into_iter()
+                .map(|chart| (chart, CardState::Open))
+                .collect(),
+        }
+    }
+
+    pub fn cards(&self) -> &[(DrawnChart, CardState)] {
+        &self.cards
+    }
+
+    pub fn protect(&mut self, card: usize, player: usize) -> Result<(), DrawError> {
+        let state = self.open_card(card)?;
+        *state = CardState::Protected { player };
+        Ok(())
+    }
+
+    pub fn veto(&mut self, card: usize, player: usize) -> Result<(), DrawError> {
+        let state = self.open_card(card)?;
+        *state = CardState::Vetoed { player };
+        Ok(())
+    }
+
+    /// Undoes a protect or veto
+    pub fn reset(&mut self, card: usize) -> Result<(), DrawError> {
+        let (_, state) = self
+            .cards
+            .get_mut(card)
+            .ok_or(DrawError::NoSuchCard(card))?;
+        *state = CardState::Open;
+        Ok(())
+    }
+
+    /// Every chart that hasn't been vetoed, i.e. the ones left to play
+    pub fn remaining(&self) -> impl Iterator<Item = &DrawnChart> {
+        self.cards
+            .iter()
+            .filter(|(_, state)| !matches!(state, CardState::Vetoed { .. }))
+            .map(|(chart, _)| chart)
+    }
+
+    fn open_card(&mut self, card: usize) -> Result<&mut CardState, DrawError> {
+        let (_, state) = self
+            .cards
+            .get_mut(card)
+            .ok_or(DrawError::NoSuchCard(card))?;
+        match state {
+            CardState::Open => Ok(state),
+            CardState::Protected { .. } => Err(DrawError::AlreadyProtected(card)),
+            CardState::Vetoed { .. } => Err(DrawError::AlreadyVetoed(card)),
+        }
+    }
+}
+
+/// A small seedable random number generator, so draws are reproducible
+/// without depending on the algorithm of an external crate staying the same
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`. `n` must not be 0
+    fn below(&mut self, n: u64) -> u64 {
+        ((self.next_u64() as u128 * n as u128) >> 64) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ddr_song::PlayStyle;
+    use crate::test_util::songs;
+
+    #[test]
+    fn draws_are_reproducible() {
+        let songs = songs();
+        let draw = ChartDraw {
+            filter: ChartFilter {
+                levels: Some(14..=16),
+                play_style: Some(PlayStyle::Singles),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let first = draw.draw(&songs, 3, 42).unwrap();
+        assert_eq!(first, draw.draw(&songs, 3, 42).unwrap());
+        assert!(first
+            .iter()
+            .all(|c| c.chart.play_style() == PlayStyle::Singles && (14..=16).contains(&c.level)));
+        let ids: HashSet<_> = first.iter().map(|c| &c.song_id).collect();
+        assert_eq!(ids.len(), 3);
+
+        // MAX 300 has two charts in range, but only one song can be drawn once
+        assert_eq!(
+            draw.draw(&songs, 4, 42),
+            Err(DrawError::NotEnoughCharts {
+                requested: 4,
+                available: 3
+            })
+        );
+
+        let mut draw = draw;
+        draw.exclude(&first);
+        let second = draw.draw(&songs, 1, 7).unwrap();
+        assert!(!first.contains(&second[0]));
+    }
+
+    #[test]
+    fn level_weights() {
+        let songs = songs();
+        let draw = ChartDraw {
+            filter: ChartFilter {
+                play_style: Some(PlayStyle::Doubles),
+                ..Default::default()
+            },
+            level_weights: Some(HashMap::from([(16, 1), (17, 0)])),
+            ..Default::default()
+        };
+        for seed in 0..20 {
+            let drawn = draw.draw(&songs, 1, seed).unwrap();
+            assert_eq!((drawn[0].chart, drawn[0].level), (Chart::EDP, 16));
+        }
+    }
+
+    #[test]
+    fn protects_and_vetoes() {
+        let songs = songs();
+        let drawn = ChartDraw::default().draw(&songs, 3, 1).unwrap();
+        let mut m = DrawnMatch::new(drawn);
+        m.protect(0, 1).unwrap();
+        assert_eq!(m.veto(0, 2), Err(DrawError::AlreadyProtected(0)));
+        m.veto(1, 2).unwrap();
+        assert_eq!(m.protect(1, 1), Err(DrawError::AlreadyVetoed(1)));
+        assert_eq!(m.veto(3, 1), Err(DrawError::NoSuchCard(3)));
+        assert_eq!(m.remaining().count(), 2);
+        m.reset(1).unwrap();
+        assert_eq!(m.remaining().count(), 3);
+        assert_eq!(m.cards()[0].1, CardState::Protected { player: 1 });
+    }
+}
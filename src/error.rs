@@ -2,6 +2,9 @@ pub type Result<T> = std::result::Result<T, Error>;
 
 use thiserror::Error;
 
+use crate::ddr_song::{Chart, SongId};
+use crate::website_backends::sanbai::song_details::SongDetailsParseError;
+
 #[non_exhaustive]
 #[derive(Debug, Error)]
 pub enum Error {
@@ -15,10 +18,46 @@ pub enum Error {
     SanbaiSongJsonParseError(serde_json::Error),
     #[error("Error parsing into `SanbaiScoreOuter`")]
     SanbaiScoreJsonParseError(reqwest::Error),
-    #[error("Couldn't parse the bpm html, something may have changed")]
-    SanbaiBpmHtmlParseError,
+    #[error("Couldn't parse the song details html, something may have changed")]
+    SanbaiSongDetailsParseError(#[from] SongDetailsParseError),
     #[error("Couldn't parse skill attack html, something may have changed")]
     SkillAttackHtmlParseError(&'static str),
+    #[error("Error reading or writing a local file")]
+    IoError(#[from] std::io::Error),
+    #[error("Couldn't parse local cache file")]
+    CacheParseError(serde_json::Error),
+    #[error("Couldn't parse courses.ron")]
+    CourseRonParseError(ron::error::SpannedError),
+    #[error("Couldn't parse courses.json")]
+    CourseJsonParseError(serde_json::Error),
+    #[error("Couldn't parse local song patches")]
+    PatchParseError(ron::error::SpannedError),
     #[error("Couldn't parse master song list")]
     SkillAttackTsvParseError(#[from] csv::Error),
 }
+
+/// Why a search query couldn't be parsed, worded so it can be shown to
+/// whoever typed the query
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum QueryError {
+    #[error("No song title given")]
+    MissingSongTitle,
+    #[error("Give a chart like ESP or a level like 15 after the song title")]
+    MissingChartOrLevel,
+    #[error("Only give one chart, got both {0} and {1}")]
+    TooManyCharts(Chart, Chart),
+    #[error("Couldn't understand `{filter}`: {reason}")]
+    InvalidFilter { filter: String, reason: String },
+    #[error("`{0}` isn't a song id, song url or Skill Attack index")]
+    UnknownIdentifier(String),
+}
+
+/// Why a [`ListQuery`](crate::search::ListQuery) couldn't be run over the
+/// song list, even though the query itself is fine
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ListError {
+    #[error("No song with id {0}")]
+    UnknownSong(SongId),
+    #[error("The BPM of {0} isn't known")]
+    UnknownBpm(String),
+}
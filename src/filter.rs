@@ -0,0 +1,161 @@
+use std::ops::RangeInclusive;
+
+use crate::ddr_song::unlock::UnlockCondition;
+use crate::ddr_song::{Chart, DDRSong, PlayStyle};
+use crate::website_backends::sanbai::VersionRange;
+
+/// Picks out charts from the song list, for list queries and statistics like
+/// "how many default unlocked singles 17s from A20 onward are there".
+///
+/// Every field left as `None` doesn't filter anything, and deleted songs are
+/// left out unless `include_deleted` is set.
+///
+/// # Examples
+///
+/// ```
+/// use score_websites::ddr_song::PlayStyle;
+/// use score_websites::filter::{ChartFilter, UnlockFilter};
+/// use score_websites::website_backends::sanbai::DDRVersion;
+///
+/// let new_default_unlocked_17s = ChartFilter {
+///     levels: Some(17..=17),
+///     play_style: Some(PlayStyle::Singles),
+///     versions: Some((DDRVersion::DDRA20..).into()),
+///     unlock: Some(UnlockFilter::Default),
+///     ..Default::default()
+/// };
+/// # let song_list = [];
+/// let count = new_default_unlocked_17s.charts(&song_list).count();
+/// # assert_eq!(count, 0);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ChartFilter {
+    pub levels: Option<RangeInclusive<u8>>,
+    pub play_style: Option<PlayStyle>,
+    pub versions: Option<VersionRange>,
+    /// Main BPM of the song. Songs whose BPM isn't known don't pass
+    pub bpms: Option<RangeInclusive<u16>>,
+    pub include_deleted: bool,
+    pub unlock: Option<UnlockFilter>,
+}
+
+/// Filters charts by how they are unlocked
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UnlockFilter {
+    /// Only charts that are available without unlocking anything
+    Default,
+    /// Only charts that have to be unlocked somehow
+    Locked,
+    /// Only charts with this specific unlock condition
+    Condition(UnlockCondition),
+}
+
+impl UnlockFilter {
+    pub fn matches(&self, condition: UnlockCondition) -> bool {
+        match self {
+            UnlockFilter::Default => condition.is_default(),
+            UnlockFilter::Locked => condition.is_locked(),
+            UnlockFilter::Condition(c) => *c == condition,
+        }
+    }
+}
+
+impl ChartFilter {
+    /// `true` if the chart of this song at this level passes the filter
+    pub fn matches(&self, song: &DDRSong, chart: Chart, level: u8) -> bool {
+        if song.deleted && !self.include_deleted {
+            return false;
+        }
+        if let Some(levels) = &self.levels {
+            if !levels.contains(&level) {
+                return false;
+            }
+        }
+        if let Some(play_style) = self.play_style {
+            if chart.play_style() != play_style {
+                return false;
+            }
+        }
+        if let Some(versions) = &self.versions {
+            if !versions.contains(song.version_num) {
+                return false;
+            }
+        }
+        if let Some(bpms) = &self.bpms {
+            match song.bpm {
+                Some(bpm) if bpms.contains(&bpm.get_main_bpm()) => {}
+                _ => return false,
+            }
+        }
+        if let Some(unlock) = &self.unlock {
+            if !unlock.matches(song.unlock_condition(chart)) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Every chart of the songs that passes the filter, along with its level
+    pub fn charts<'f, 'a: 'f>(
+        &'f self,
+        songs: impl IntoIterator<Item = &'a DDRSong> + 'f,
+    ) -> impl Iterator<Item = (&'a DDRSong, Chart, u8)> + 'f {
+        songs.into_iter().flat_map(move |song| {
+            song.ratings
+                .charts()
+                .filter(move |&(chart, level)| self.matches(song, chart, level))
+                .map(move |(chart, level)| (song, chart, level))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::song;
+
+    #[test]
+    fn default_unlocked_17s() {
+        let songs = [
+            song(
+                "6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q",
+                "song",
+                [4, 8, 13, 17, 18, 8, 13, 17, 18],
+            )
+            .lock_types([0, 0, 0, 0, 30, 0, 0, 0, 30])
+            .build(),
+            song(
+                "0bq9qI9PoPIlQl89bDO60o9q8I1iIP66",
+                "song",
+                [4, 8, 12, 17, 0, 8, 12, 16, 0],
+            )
+            .lock_types([40, 40, 40, 40, 0, 40, 40, 40, 0])
+            .build(),
+            song(
+                "ld6P1lbb0bPO9doqbbPOoPb8qoDo8id0",
+                "song",
+                [4, 8, 12, 17, 0, 8, 12, 17, 0],
+            )
+            .deleted()
+            .lock_types([0; 9])
+            .build(),
+        ];
+        let mut filter = ChartFilter {
+            levels: Some(17..=17),
+            play_style: Some(PlayStyle::Singles),
+            unlock: Some(UnlockFilter::Default),
+            ..Default::default()
+        };
+        let charts: Vec<_> = filter.charts(&songs).map(|(_, c, l)| (c, l)).collect();
+        assert_eq!(charts, [(Chart::ESP, 17)]);
+
+        filter.unlock = Some(UnlockFilter::Locked);
+        assert_eq!(filter.charts(&songs).count(), 1);
+
+        filter.unlock = None;
+        filter.include_deleted = true;
+        filter.play_style = None;
+        filter.levels = Some(17..=18);
+        assert_eq!(filter.charts(&songs).count(), 7);
+    }
+}
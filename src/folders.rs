@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::ddr_song::{Chart, DDRSong};
+use crate::filter::ChartFilter;
+use crate::scores::{LampType, Player, ScoreRow};
+use crate::website_backends::sanbai::DDRVersion;
+
+/// How to group charts into folders, like the song select of the game
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Grouping {
+    Level,
+    /// Newest version first, like in game
+    Version,
+    TitleInitial,
+    /// Bands of main BPM `width` wide, e.g. 150-174 with a width of 25
+    BpmBand {
+        width: u16,
+    },
+}
+
+/// The folder a chart is sorted into
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FolderKey {
+    Level(u8),
+    Version(std::cmp::Reverse<DDRVersion>),
+    TitleInitial(char),
+    /// Inclusive main BPM range
+    BpmBand {
+        lower: u16,
+        upper: u16,
+    },
+    /// Songs whose BPM hasn't been fetched, sorted after every BPM band
+    UnknownBpm,
+}
+
+impl fmt::Display for FolderKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FolderKey::Level(level) => write!(f, "Level {}", level),
+            FolderKey::Version(version) => write!(f, "{}", version.0),
+            FolderKey::TitleInitial('#') => write!(f, "Number/Symbol"),
+            FolderKey::TitleInitial(initial) => write!(f, "{}", initial),
+            FolderKey::BpmBand { lower, upper } => write!(f, "BPM {}-{}", lower, upper),
+            FolderKey::UnknownBpm => write!(f, "BPM ???"),
+        }
+    }
+}
+
+/// A chart in a folder, with the score the player has on it
+#[derive(Debug, Clone)]
+pub struct FolderChart<'a> {
+    pub song: &'a DDRSong,
+    pub chart: Chart,
+    pub level: u8,
+    /// `None` if the player hasn't played the chart, or no player was given
+    pub score: Option<ScoreRow>,
+}
+
+impl FolderChart<'_> {
+    pub fn lamp(&self) -> Option<LampType> {
+        self.score.map(|s| s.lamp)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Folder<'a> {
+    pub key: FolderKey,
+    /// Sorted by title like in game, then by chart
+    pub charts: Vec<FolderChart<'a>>,
+}
+
+impl Folder<'_> {
+    /// How many charts have each lamp. Unplayed charts are counted under `None`
+    pub fn lamp_counts(&self) -> BTreeMap<Option<LampType>, usize> {
+        let mut counts = BTreeMap::new();
+        for chart in &self.charts {
+            *counts.entry(chart.lamp()).or_default() += 1;
+        }
+        counts
+    }
+
+    /// How many charts have `lamp` or better, e.g. every full combo for
+    /// [`LampType::GoodCombo`]
+    pub fn num_at_least(&self, lamp: LampType) -> usize {
+        self.charts
+            .iter()
+            .filter(|c| c.lamp().is_some_and(|l| l >= lamp))
+            .count()
+    }
+}
+
+impl Grouping {
+    /// The folder the chart belongs in
+    pub fn key(&self, song: &DDRSong, level: u8) -> FolderKey {
+        match *self {
+            Grouping::Level => FolderKey::Level(level),
+            Grouping::Version => FolderKey::Version(std::cmp::Reverse(song.version_num)),
+            Grouping::TitleInitial => FolderKey::TitleInitial(song.title_initial),
+            Grouping::BpmBand { width } => match song.bpm {
+                Some(bpm) => {
+                    let width = width.max(1);
+                    let lower = bpm.get_main_bpm() / width * width;
+                    FolderKey::BpmBand {
+                        lower,
+                        upper: lower.saturating_add(width - 1),
+                    }
+                }
+                None => FolderKey::UnknownBpm,
+            },
+        }
+    }
+
+    /// Groups every chart passing the filter into folders, in the order
+    /// the game shows them. Empty folders are left out. If a player is
+    /// given, each chart has their score on it
+    pub fn folders<'a>(
+        &self,
+        songs: &'a [DDRSong],
+        filter: &ChartFilter,
+        player: Option<&Player>,
+    ) -> Vec<Folder<'a>> {
+        let mut folders: BTreeMap<FolderKey, Vec<FolderChart<'a>>> = BTreeMap::new();
+        for (song, chart, level) in filter.charts(songs) {
+            let score = player
+                .and_then(|p| p.scores.get(&song.song_id))
+                .and_then(|scores| scores[chart as usize]);
+            folders
+                .entry(self.key(song, level))
+                .or_default()
+                .push(FolderChart {
+                    song,
+                    chart,
+                    level,
+                    score,
+                });
+        }
+        folders
+            .into_iter()
+            .map(|(key, mut charts)| {
+                charts.sort_by_cached_key(|c| {
+                    (
+                        c.song.title_initial,
+                        c.song.song_name.to_lowercase(),
+                        c.chart as u8,
+                    )
+                });
+                Folder { key, charts }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ddr_song::{PlayStyle, SongId};
+    use crate::scores::Scores;
+    use crate::test_util::songs;
+
+    #[test]
+    fn group_by_level_with_lamps() {
+        let songs = songs();
+        let mut player = Player::new("player", 12345678, None::<String>);
+        let scores = Scores {
+            expert_score: Some(ScoreRow {
+                score: 990_000,
+                lamp: LampType::PerfectCombo,
+                time_played: None,
+            }),
+            ..Default::default()
+        };
+        player.scores.insert(songs[1].song_id.clone(), scores);
+
+        let filter = ChartFilter {
+            play_style: Some(PlayStyle::Singles),
+            ..Default::default()
+        };
+        let folders = Grouping::Level.folders(&songs, &filter, Some(&player));
+        let keys: Vec<_> = folders.iter().map(|f| f.key).collect();
+        assert_eq!(
+            keys,
+            [3, 4, 5, 7, 8, 10, 11, 12, 14, 16].map(FolderKey::Level)
+        );
+
+        let sixteens = folders.last().unwrap();
+        let names: Vec<_> = sixteens.charts.iter().map(|c| &*c.song.song_name).collect();
+        assert_eq!(names, ["888", "MAX 300"]);
+        assert_eq!(sixteens.num_at_least(LampType::GoodCombo), 1);
+        assert_eq!(sixteens.lamp_counts()[&None], 1);
+        assert_eq!(sixteens.lamp_counts()[&Some(LampType::PerfectCombo)], 1);
+    }
+
+    #[test]
+    fn group_by_version_initial_and_bpm() {
+        let songs = songs();
+        let filter = ChartFilter::default();
+
+        let versions: Vec<_> = Grouping::Version
+            .folders(&songs, &filter, None)
+            .iter()
+            .map(|f| f.key.to_string())
+            .collect();
+        assert_eq!(versions.len(), 3);
+        assert_eq!(versions[2], DDRVersion::DDR1stMIX.to_string());
+
+        let initials: Vec<_> = Grouping::TitleInitial
+            .folders(&songs, &filter, None)
+            .iter()
+            .map(|f| f.key)
+            .collect();
+        assert_eq!(initials, ['#', 'M', 'P'].map(FolderKey::TitleInitial));
+
+        let bands = Grouping::BpmBand { width: 50 }.folders(&songs, &filter, None);
+        let keys: Vec<_> = bands.iter().map(|f| f.key.to_string()).collect();
+        assert_eq!(keys, ["BPM 150-199", "BPM 300-349", "BPM ???"]);
+        assert_eq!(bands[0].charts.len(), 7);
+        assert!(bands[0].charts.iter().all(|c| c.song.song_id
+            == "6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q"
+                .parse::<SongId>()
+                .unwrap()));
+    }
+}
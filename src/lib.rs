@@ -1,26 +1,61 @@
+/// Local caches of data fetched from the websites
+pub mod cache;
 /// Things related to a course in DDR
 pub mod courses;
 /// DDR song representation and searching
 pub mod ddr_song;
+/// Random chart draws for tournaments
+pub mod draw;
 /// Error enum
 pub mod error;
+/// Filters for picking charts out of the song list
+pub mod filter;
+/// Grouping charts into folders like the song select of the game
+pub mod folders;
+/// List queries over the song list combined with the scores of a player
+pub mod score_query;
 /// Structures and methods related to storing the scores of players
 pub mod scores;
 /// Utilities to search the song list for a specific song
 pub mod search;
+/// Speed mod recommendations for a target read speed
+pub mod speed_mod;
 /// The backend logic for querying and parsing of DDR score websites
 pub mod website_backends;
 
+#[cfg(test)]
+pub(crate) mod test_util;
+
 use std::collections::HashMap;
 
 use futures::stream::FuturesUnordered;
 /// `reqwest`'s async http client re-exported.
 pub use reqwest::Client as HttpClient;
+use time::OffsetDateTime;
 use tokio_stream::StreamExt;
-use tracing::warn;
+use tracing::{info, warn};
 
-use crate::ddr_song::SongId;
-use crate::website_backends::sanbai::{get_sanbai_scores, get_sanbai_song_data};
+use crate::cache::{BpmCache, CachedSong, JacketCache, JacketReport};
+use crate::courses::{Course, CourseCatalog};
+use crate::ddr_song::diff::SongListDiff;
+use crate::ddr_song::normalize::MismatchReport;
+use crate::ddr_song::unlock::LockTypeTable;
+use crate::ddr_song::{Bpm, Chart, SongId};
+use crate::draw::{ChartDraw, DrawError, DrawnChart};
+use crate::error::ListError;
+use crate::filter::ChartFilter;
+use crate::folders::{Folder, Grouping};
+use crate::score_query::{ScorePage, ScoreQuery};
+use crate::search::fuzzy::FuzzyConfig;
+use crate::search::{
+    ListQuery, ListResult, RankedSearchResult, SearchIndex, SearchOutcome, SearchQuery,
+    SongIdentifier,
+};
+use crate::website_backends::patch::SongPatches;
+use crate::website_backends::sanbai::song_details::{
+    get_sanbai_song_details, get_sanbai_song_details_batch, SongDetails,
+};
+use crate::website_backends::sanbai::{get_sanbai_scores, get_sanbai_song_data, JacketSize};
 use crate::website_backends::skill_attack;
 use ddr_song::DDRSong;
 use scores::Player;
@@ -32,7 +67,17 @@ pub use error::Result;
 #[derive(Clone, Debug)]
 pub struct DDRDatabase {
     songs: Vec<DDRSong>,
+    /// The song list as the websites gave it, before anything local was
+    /// filled in. Song list diffs compare these, and the song list is
+    /// rebuilt from it when the patches change
+    raw_songs: Vec<DDRSong>,
     players: Vec<Player>,
+    bpm_cache: BpmCache,
+    mismatch_report: MismatchReport,
+    last_song_list_diff: SongListDiff,
+    lock_type_table: LockTypeTable,
+    search_index: SearchIndex,
+    patches: SongPatches,
 }
 
 impl DDRDatabase {
@@ -40,7 +85,14 @@ impl DDRDatabase {
     pub async fn new(http: HttpClient, players: impl Into<Vec<Player>>) -> Result<Self> {
         let mut db = Self {
             songs: vec![],
+            raw_songs: vec![],
             players: players.into(),
+            bpm_cache: BpmCache::default(),
+            mismatch_report: MismatchReport::default(),
+            last_song_list_diff: SongListDiff::default(),
+            lock_type_table: LockTypeTable::default(),
+            search_index: SearchIndex::default(),
+            patches: SongPatches::default(),
         };
         db.update_scores(http).await?;
         Ok(db)
@@ -108,7 +160,8 @@ impl DDRDatabase {
                                 .iter()
                                 .map(|song| DDRSong::new_from_sanbai_and_skillattack(song, None))
                                 .collect();
-                            self.songs = song_list_without_skill_attack;
+                            self.set_song_list(song_list_without_skill_attack);
+                            self.mismatch_report = MismatchReport::default();
                             warn!("ERROR: {:?}", e);
                             warn!("ERROR: Skill attack seems to be down, or has changed its format");
                             skip_skill_attack = true;
@@ -116,15 +169,14 @@ impl DDRDatabase {
                         }
                     };
                     // let skill_attack_songs = skill_attack_songs.expect("sa song task panicked")?;
-                    let new_song_list = DDRSong::from_combining_song_lists(&sanbai_songs, &skill_attack_songs);
-                    num_new_songs = match new_song_list.len().checked_sub(self.songs.len()) {
-                        Some(n) => n,
-                        None => {
-                            warn!("New song list has fewer songs than old song list!");
-                            0
-                        }
-                    };
-                    self.songs = new_song_list;
+                    let (new_song_list, mismatch_report) =
+                        DDRSong::from_combining_song_lists_with_report(&sanbai_songs, &skill_attack_songs);
+                    self.mismatch_report = mismatch_report;
+                    self.set_song_list(new_song_list);
+                    num_new_songs = self.last_song_list_diff.added.len();
+                    if !self.last_song_list_diff.removed.is_empty() {
+                        warn!("New song list is missing songs from the old song list!");
+                    }
                     sa_songs_updated = true;
 
                 },
@@ -166,15 +218,268 @@ impl DDRDatabase {
         Ok((num_new_songs, num_new_scores))
     }
 
+    /// Replaces the song list, filling in everything we have cached locally
+    fn set_song_list(&mut self, songs: Vec<DDRSong>) {
+        self.last_song_list_diff = SongListDiff::new(&self.raw_songs, &songs);
+        self.raw_songs = songs;
+        self.rebuild_song_list();
+    }
+
+    /// Builds the song list from the raw one again, so nothing is left over
+    /// from patches that were since removed
+    fn rebuild_song_list(&mut self) {
+        self.songs = self.raw_songs.clone();
+        self.apply_bpm_cache();
+        self.apply_lock_type_table();
+        self.apply_patches();
+    }
+
+    /// Patches the song list and rebuilds the search index, since patches can
+    /// add search names
+    fn apply_patches(&mut self) {
+        for song_id in self.patches.apply(&mut self.songs) {
+            warn!("Song patch for {} doesn't match any song", song_id);
+        }
+        self.search_index = SearchIndex::new(&self.songs);
+    }
+
+    fn apply_lock_type_table(&mut self) {
+        for song in &mut self.songs {
+            song.decode_lock_types(&self.lock_type_table);
+        }
+    }
+
+    fn apply_bpm_cache(&mut self) {
+        for song in &mut self.songs {
+            if let Some(bpm) = self.patches.bpm(&song.song_id) {
+                song.bpm = Some(bpm);
+            } else if let Some(bpm) = self.bpm_cache.get(&song.song_id) {
+                song.bpm = bpm;
+            }
+            if song.artist.is_none() {
+                if let Some(artist) = self.bpm_cache.artist(&song.song_id) {
+                    song.set_artist(artist.to_string());
+                }
+            }
+        }
+    }
+
     /// A list of all the songs
     pub fn song_list(&self) -> &[DDRSong] {
         &self.songs
     }
 
+    /// The search index of the song list, rebuilt whenever the song list changes
+    pub fn search_index(&self) -> &SearchIndex {
+        &self.search_index
+    }
+
+    /// Builds every course of the catalog from the song list, with at most
+    /// `max_concurrent` requests in flight for BPMs that aren't cached, see
+    /// [`CourseCatalog::build_courses`]
+    pub async fn build_courses(
+        &self,
+        http: HttpClient,
+        catalog: &CourseCatalog,
+        max_concurrent: usize,
+    ) -> Vec<Course> {
+        catalog
+            .build_courses(http, &self.songs, max_concurrent)
+            .await
+    }
+
+    /// Looks up a song by its id, url or Skill Attack index without searching
+    pub fn song_by_identifier(&self, identifier: &SongIdentifier) -> Option<&DDRSong> {
+        self.search_index
+            .position(identifier)
+            .map(|i| &self.songs[i])
+    }
+
+    /// Searches the song list through the search index, best match first
+    pub fn search(&self, query: &SearchQuery) -> Vec<RankedSearchResult<'_>> {
+        query.search_indexed(&self.search_index, &self.songs, &FuzzyConfig::default())
+    }
+
+    /// Searches like [`DDRDatabase::search`], telling apart a missing chart
+    /// from a missing song, see [`SearchOutcome`]
+    pub fn search_outcome(&self, query: &SearchQuery) -> SearchOutcome<'_> {
+        query.search_outcome(&self.search_index, &self.songs, &FuzzyConfig::default())
+    }
+
+    /// Every song fitting a level or BPM range query, see [`ListQuery`]
+    pub fn list(
+        &self,
+        query: &ListQuery,
+        filter: &ChartFilter,
+    ) -> std::result::Result<Vec<ListResult<'_>>, ListError> {
+        query.run(&self.songs, filter)
+    }
+
+    /// The charts fitting a score query for one of the players, see [`ScoreQuery`]
+    pub fn score_query(&self, query: &ScoreQuery, player: &Player) -> ScorePage<'_> {
+        query.run(&self.songs, player)
+    }
+
+    /// The songs sanbai and Skill Attack disagreed about in the last song list
+    /// update. Empty if Skill Attack couldn't be reached
+    pub fn mismatch_report(&self) -> &MismatchReport {
+        &self.mismatch_report
+    }
+
+    /// What changed in the last song list update, like new songs and rerates.
+    /// After the first update every song counts as added
+    pub fn last_song_list_diff(&self) -> &SongListDiff {
+        &self.last_song_list_diff
+    }
+
+    /// Every chart in the song list that passes the filter, along with its level
+    pub fn charts<'a>(
+        &'a self,
+        filter: &'a ChartFilter,
+    ) -> impl Iterator<Item = (&'a DDRSong, Chart, u8)> + 'a {
+        filter.charts(&self.songs)
+    }
+
+    /// Draws `count` random charts from the song list, see [`ChartDraw`]
+    pub fn draw_charts(
+        &self,
+        draw: &ChartDraw,
+        count: usize,
+        seed: u64,
+    ) -> std::result::Result<Vec<DrawnChart>, DrawError> {
+        draw.draw(&self.songs, count, seed)
+    }
+
+    /// Groups every chart passing the filter into in game style folders, with
+    /// the scores of the player if one is given
+    pub fn folders(
+        &self,
+        grouping: Grouping,
+        filter: &ChartFilter,
+        player: Option<&Player>,
+    ) -> Vec<Folder<'_>> {
+        grouping.folders(&self.songs, filter, player)
+    }
+
+    /// The table used to decode sanbai lock types into unlock conditions
+    pub fn lock_type_table(&self) -> &LockTypeTable {
+        &self.lock_type_table
+    }
+
+    /// Replaces the lock type table and decodes the unlock conditions of
+    /// every song again
+    pub fn set_lock_type_table(&mut self, lock_type_table: LockTypeTable) {
+        self.lock_type_table = lock_type_table;
+        self.apply_lock_type_table();
+    }
+
+    pub fn patches(&self) -> &SongPatches {
+        &self.patches
+    }
+
+    /// Replaces the song patches and rebuilds the song list with them, so
+    /// removed patches are undone right away. They are applied again after
+    /// every song list update
+    pub fn set_patches(&mut self, patches: SongPatches) {
+        self.patches = patches;
+        self.rebuild_song_list();
+    }
+
     /// A list of the users
     pub fn players(&self) -> &[Player] {
         &self.players
     }
+
+    /// Fetches the sanbai song details of every song in the song list,
+    /// with at most `max_concurrent` requests in flight at once
+    pub async fn fetch_song_details(
+        &self,
+        http: HttpClient,
+        max_concurrent: usize,
+    ) -> Vec<(SongId, Result<SongDetails>)> {
+        let song_ids = self.songs.iter().map(|s| s.song_id.clone());
+        get_sanbai_song_details_batch(http, song_ids, max_concurrent).await
+    }
+
+    /// Downloads the jackets of every song in each of the given sizes into
+    /// `cache`, skipping any that are already cached
+    pub async fn fetch_jackets(
+        &self,
+        http: HttpClient,
+        cache: &JacketCache,
+        sizes: &[JacketSize],
+        max_concurrent: usize,
+    ) -> JacketReport {
+        let song_ids = self.songs.iter().map(|s| s.song_id.clone());
+        cache.fetch(http, song_ids, sizes, max_concurrent).await
+    }
+
+    /// The local BPM cache, e.g. for saving it with [`BpmCache::save`]
+    pub fn bpm_cache(&self) -> &BpmCache {
+        &self.bpm_cache
+    }
+
+    /// Replaces the local BPM cache and fills in the BPM of every song it contains
+    pub fn set_bpm_cache(&mut self, bpm_cache: BpmCache) {
+        self.bpm_cache = bpm_cache;
+        self.apply_bpm_cache();
+    }
+
+    /// Fetches the BPM of every song that isn't in the BPM cache yet, with at
+    /// most `max_concurrent` requests in flight at once. Songs with a patched
+    /// BPM are skipped, and songs we didn't get a BPM for last time are only
+    /// fetched again after [`BpmCache::RETRY_AFTER`].
+    /// Returns the number of songs fetched, failed or not, so the cache
+    /// changed if it isn't 0
+    pub async fn update_bpms(&mut self, http: HttpClient, max_concurrent: usize) -> usize {
+        let now = OffsetDateTime::now_utc();
+        let new_song_ids: Vec<_> = self
+            .songs
+            .iter()
+            .filter(|s| self.patches.bpm(&s.song_id).is_none())
+            .filter(|s| self.bpm_cache.needs_fetch(&s.song_id, now))
+            .map(|s| s.song_id.clone())
+            .collect();
+        if new_song_ids.is_empty() {
+            return 0;
+        }
+        info!("Fetching bpm of {} uncached songs", new_song_ids.len());
+
+        // failures are already logged by the batch fetch
+        let fetched = get_sanbai_song_details_batch(http, new_song_ids, max_concurrent).await;
+        let num_fetched = fetched.len();
+        for (song_id, details) in fetched {
+            match details {
+                Ok(details) => self
+                    .bpm_cache
+                    .insert(song_id, CachedSong::from(&details), now),
+                Err(_) => self.bpm_cache.insert_failure(song_id, now),
+            }
+        }
+        // Goes over the song list once, rather than once per fetched song
+        self.apply_bpm_cache();
+        num_fetched
+    }
+
+    /// Gets the BPM of a song, only fetching it from sanbai if it isn't cached yet
+    pub async fn song_bpm(&mut self, http: HttpClient, song_id: &SongId) -> Result<Option<Bpm>> {
+        if let Some(bpm) = self.patches.bpm(song_id) {
+            return Ok(Some(bpm));
+        }
+        if let Some(bpm) = self.bpm_cache.get(song_id) {
+            return Ok(bpm);
+        }
+        let details = get_sanbai_song_details(http, song_id).await?;
+        self.bpm_cache.insert(
+            song_id.clone(),
+            CachedSong::from(&details),
+            OffsetDateTime::now_utc(),
+        );
+        if let Some(song) = self.songs.iter_mut().find(|s| &s.song_id == song_id) {
+            song.apply_song_details(&details);
+        }
+        Ok(details.bpm)
+    }
 }
 
 // Helper function to reduce code duplication
@@ -215,8 +520,79 @@ fn process_skill_attack_score(
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::test_util::song;
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    /// A database without players that hasn't fetched anything
+    fn offline_db() -> DDRDatabase {
+        DDRDatabase {
+            songs: vec![],
+            raw_songs: vec![],
+            players: vec![],
+            bpm_cache: BpmCache::default(),
+            mismatch_report: MismatchReport::default(),
+            last_song_list_diff: SongListDiff::default(),
+            lock_type_table: LockTypeTable::default(),
+            search_index: SearchIndex::default(),
+            patches: SongPatches::default(),
+        }
+    }
+
+    #[test]
+    fn refresh_keeps_cached_artists() {
+        let id = "6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q";
+        let mut bpm_cache = BpmCache::default();
+        bpm_cache.insert(
+            id.parse().unwrap(),
+            CachedSong {
+                bpm: Some(Bpm::Constant(150)),
+                artist: Some("NAOKI".into()),
+            },
+            OffsetDateTime::now_utc(),
+        );
+        let mut db = offline_db();
+        db.set_bpm_cache(bpm_cache);
+
+        // A refreshed song list only has artists from Skill Attack
+        db.set_song_list(vec![song(id, "PARANOiA", [0; 9]).build()]);
+        let paranoia = &db.song_list()[0];
+        assert_eq!(paranoia.artist.as_deref(), Some("NAOKI"));
+        assert_eq!(paranoia.artist_search_names, ["naoki"]);
+        assert_eq!(paranoia.bpm, Some(Bpm::Constant(150)));
+
+        db.set_song_list(vec![song(id, "PARANOiA", [0; 9]).artist("180").build()]);
+        assert_eq!(db.song_list()[0].artist.as_deref(), Some("180"));
+    }
+
+    #[test]
+    fn patches_dont_show_up_in_diffs() {
+        let id = "6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q";
+        let patches = SongPatches::from_ron(&format!(
+            r#"(songs: {{"{}": (display_name: "PARANOiA (Pippi)", deleted: true)}})"#,
+            id
+        ))
+        .unwrap();
+        let mut db = offline_db();
+        db.set_patches(patches);
+
+        db.set_song_list(vec![song(id, "PARANOiA", [0; 9]).build()]);
+        assert_eq!(db.last_song_list_diff().added.len(), 1);
+        assert!(db.song_list()[0].deleted);
+        for _ in 0..2 {
+            db.set_song_list(vec![song(id, "PARANOiA", [0; 9]).build()]);
+            assert_eq!(db.last_song_list_diff(), &SongListDiff::default());
+        }
+
+        // Removing the patch undoes it without waiting for a refresh
+        db.set_patches(SongPatches::default());
+        let paranoia = &db.song_list()[0];
+        assert!(!paranoia.deleted);
+        assert_eq!(paranoia.song_name, "PARANOiA");
+        assert_eq!(paranoia.search_names, ["paranoia"]);
+    }
 }
@@ -0,0 +1,345 @@
+use std::cmp::Ordering;
+
+use crate::ddr_song::{Chart, DDRSong};
+use crate::filter::ChartFilter;
+use crate::scores::{LampType, Player, ScoreRow};
+
+/// A list query over the charts passing a [`ChartFilter`], narrowed down by
+/// the scores of a player, like "my unplayed 15s" or "16s I've cleared but
+/// not full combo'd".
+///
+/// # Examples
+///
+/// ```
+/// use score_websites::filter::ChartFilter;
+/// use score_websites::score_query::{ScoreCondition, ScoreQuery, ScoreSort};
+/// use score_websites::scores::Player;
+///
+/// let fourteens_under_950k = ScoreQuery {
+///     filter: ChartFilter {
+///         levels: Some(14..=14),
+///         ..Default::default()
+///     },
+///     conditions: vec![ScoreCondition::ScoreBelow(950_000)],
+///     sort: ScoreSort::Score,
+///     per_page: Some(10),
+///     ..Default::default()
+/// };
+/// # let song_list = [];
+/// let player = Player::new("MARK", 51527130, None::<String>);
+/// let page = fourteens_under_950k.run(&song_list, &player);
+/// # assert_eq!(page.total, 0);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ScoreQuery<'a> {
+    pub filter: ChartFilter,
+    /// Every condition has to hold for a chart to be listed
+    pub conditions: Vec<ScoreCondition<'a>>,
+    pub sort: ScoreSort,
+    /// Sorts from highest to lowest instead
+    pub descending: bool,
+    /// Splits the results into pages of this many charts, or returns every
+    /// chart at once if `None`
+    pub per_page: Option<usize>,
+    /// Which page to return, starting from 0
+    pub page: usize,
+}
+
+/// A condition on the score of the player on a chart
+#[derive(Debug, Clone, Copy)]
+pub enum ScoreCondition<'a> {
+    /// The player has no score on the chart
+    Unplayed,
+    Played,
+    /// Passed, but without a full combo
+    ClearedNotFullCombo,
+    /// Played with at least this lamp
+    LampAtLeast(LampType),
+    /// Unplayed, or played with a worse lamp than this
+    LampBelow(LampType),
+    /// Played with a score below this. Unplayed charts don't count
+    ScoreBelow(u32),
+    /// Played with a score of at least this
+    ScoreAtLeast(u32),
+    /// The other player has a higher score than the player, or has played a
+    /// chart the player hasn't
+    BeatenBy(&'a Player),
+}
+
+/// What a [`ScoreQuery`] is sorted by. Ties keep the song list order, then
+/// chart order
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ScoreSort {
+    #[default]
+    Level,
+    Title,
+    /// Unplayed charts come before any score
+    Score,
+    /// Unplayed charts come before any lamp
+    Lamp,
+    /// Charts played longest ago first, and unplayed charts before those
+    TimePlayed,
+}
+
+/// A chart found by a [`ScoreQuery`], with the score of the player on it
+#[derive(Debug, Clone)]
+pub struct ScoredChart<'a> {
+    pub song: &'a DDRSong,
+    pub chart: Chart,
+    pub level: u8,
+    /// `None` if the player hasn't played the chart
+    pub score: Option<ScoreRow>,
+}
+
+/// One page of the charts found by a [`ScoreQuery`]
+#[derive(Debug, Clone)]
+pub struct ScorePage<'a> {
+    pub charts: Vec<ScoredChart<'a>>,
+    /// The number of charts found over every page
+    pub total: usize,
+    pub page: usize,
+    /// At least 1, even when nothing was found
+    pub num_pages: usize,
+}
+
+impl ScoreCondition<'_> {
+    /// `true` if `score`, the score of the player on the chart, passes
+    pub fn matches(&self, song: &DDRSong, chart: Chart, score: Option<ScoreRow>) -> bool {
+        let lamp = score.map(|s| s.lamp);
+        match *self {
+            ScoreCondition::Unplayed => score.is_none(),
+            ScoreCondition::Played => score.is_some(),
+            ScoreCondition::ClearedNotFullCombo => {
+                lamp.is_some_and(|l| l.is_clear() && !l.is_full_combo())
+            }
+            ScoreCondition::LampAtLeast(at_least) => lamp.is_some_and(|l| l >= at_least),
+            ScoreCondition::LampBelow(below) => lamp.is_none_or(|l| l < below),
+            ScoreCondition::ScoreBelow(below) => score.is_some_and(|s| s.score < below),
+            ScoreCondition::ScoreAtLeast(at_least) => score.is_some_and(|s| s.score >= at_least),
+            ScoreCondition::BeatenBy(other) => {
+                let other_score = other
+                    .scores
+                    .get(&song.song_id)
+                    .and_then(|scores| scores[chart as usize]);
+                match (other_score, score) {
+                    (Some(other), Some(ours)) => other.score > ours.score,
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                }
+            }
+        }
+    }
+}
+
+impl ScoreQuery<'_> {
+    /// Runs the query over the song list with the scores of `player`
+    pub fn run<'s>(&self, songs: &'s [DDRSong], player: &Player) -> ScorePage<'s> {
+        let mut charts: Vec<ScoredChart<'s>> = songs
+            .iter()
+            .flat_map(|song| {
+                let scores = player.scores.get(&song.song_id);
+                song.ratings
+                    .charts()
+                    .filter(|&(chart, level)| self.filter.matches(song, chart, level))
+                    .map(move |(chart, level)| ScoredChart {
+                        song,
+                        chart,
+                        level,
+                        score: scores.and_then(|scores| scores[chart as usize]),
+                    })
+            })
+            .filter(|c| {
+                self.conditions
+                    .iter()
+                    .all(|condition| condition.matches(c.song, c.chart, c.score))
+            })
+            .collect();
+
+        charts.sort_by(|a, b| {
+            let ordering = self.sort.compare(a, b);
+            if self.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+
+        let total = charts.len();
+        let (page, num_pages) = match self.per_page {
+            Some(per_page) if per_page > 0 => {
+                charts = charts
+                    .into_iter()
+                    .skip(self.page.saturating_mul(per_page))
+                    .take(per_page)
+                    .collect();
+                (self.page, total.div_ceil(per_page).max(1))
+            }
+            _ => (0, 1),
+        };
+        ScorePage {
+            charts,
+            total,
+            page,
+            num_pages,
+        }
+    }
+}
+
+impl ScoreSort {
+    fn compare(&self, a: &ScoredChart, b: &ScoredChart) -> Ordering {
+        match self {
+            ScoreSort::Level => a.level.cmp(&b.level),
+            ScoreSort::Title => a.song.song_name.cmp(&b.song.song_name),
+            ScoreSort::Score => a.score.map(|s| s.score).cmp(&b.score.map(|s| s.score)),
+            ScoreSort::Lamp => a.score.map(|s| s.lamp).cmp(&b.score.map(|s| s.lamp)),
+            ScoreSort::TimePlayed => a
+                .score
+                .and_then(|s| s.time_played)
+                .cmp(&b.score.and_then(|s| s.time_played)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scores::Scores;
+    use crate::test_util::song;
+
+    fn expert(score: u32, lamp: LampType) -> Scores {
+        Scores {
+            expert_score: Some(ScoreRow {
+                score,
+                lamp,
+                time_played: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn names(page: &ScorePage) -> Vec<String> {
+        page.charts
+            .iter()
+            .map(|c| c.song.song_name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn score_conditions() {
+        let songs = [
+            song(
+                "6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q",
+                "a",
+                [1, 3, 8, 15, 0, 0, 0, 0, 0],
+            )
+            .build(),
+            song(
+                "0bq9qI9PoPIlQl89bDO60o9q8I1iIP66",
+                "b",
+                [1, 3, 8, 15, 0, 0, 0, 0, 0],
+            )
+            .build(),
+            song(
+                "ld6P1lbb0bPO9doqbbPOoPb8qoDo8id0",
+                "c",
+                [1, 3, 8, 15, 0, 0, 0, 0, 0],
+            )
+            .build(),
+        ];
+        let mut me = Player::new("me", 1, None::<String>);
+        me.scores
+            .insert(songs[0].song_id.clone(), expert(940_000, LampType::NoCombo));
+        me.scores.insert(
+            songs[1].song_id.clone(),
+            expert(990_000, LampType::PerfectCombo),
+        );
+        let mut rival = Player::new("rival", 2, None::<String>);
+        rival
+            .scores
+            .insert(songs[0].song_id.clone(), expert(950_000, LampType::Fail));
+        rival
+            .scores
+            .insert(songs[2].song_id.clone(), expert(800_000, LampType::Fail));
+
+        let run = |conditions: Vec<ScoreCondition>| {
+            let query = ScoreQuery {
+                filter: ChartFilter {
+                    levels: Some(15..=15),
+                    ..Default::default()
+                },
+                conditions,
+                ..Default::default()
+            };
+            names(&query.run(&songs, &me))
+        };
+        assert_eq!(run(vec![]), ["a", "b", "c"]);
+        assert_eq!(run(vec![ScoreCondition::Unplayed]), ["c"]);
+        assert_eq!(run(vec![ScoreCondition::ClearedNotFullCombo]), ["a"]);
+        assert_eq!(run(vec![ScoreCondition::ScoreBelow(950_000)]), ["a"]);
+        assert_eq!(
+            run(vec![ScoreCondition::LampBelow(LampType::GoodCombo)]),
+            ["a", "c"]
+        );
+        assert_eq!(run(vec![ScoreCondition::BeatenBy(&rival)]), ["a", "c"]);
+        assert_eq!(
+            run(vec![
+                ScoreCondition::BeatenBy(&rival),
+                ScoreCondition::Played
+            ]),
+            ["a"]
+        );
+    }
+
+    #[test]
+    fn sorting_and_paging() {
+        let songs = [
+            song(
+                "6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q",
+                "a",
+                [1, 3, 8, 15, 0, 0, 0, 0, 0],
+            )
+            .build(),
+            song(
+                "0bq9qI9PoPIlQl89bDO60o9q8I1iIP66",
+                "b",
+                [2, 4, 9, 14, 0, 0, 0, 0, 0],
+            )
+            .build(),
+        ];
+        let mut me = Player::new("me", 1, None::<String>);
+        me.scores
+            .insert(songs[0].song_id.clone(), expert(900_000, LampType::NoCombo));
+        me.scores.insert(
+            songs[1].song_id.clone(),
+            expert(990_000, LampType::PerfectCombo),
+        );
+
+        let mut query = ScoreQuery {
+            sort: ScoreSort::Score,
+            descending: true,
+            per_page: Some(3),
+            ..Default::default()
+        };
+        let page = query.run(&songs, &me);
+        assert_eq!((page.total, page.num_pages), (8, 3));
+        assert_eq!(names(&page), ["b", "a", "a"]);
+        assert_eq!(page.charts[0].chart, Chart::ESP);
+
+        query.page = 2;
+        let page = query.run(&songs, &me);
+        assert_eq!(page.charts.len(), 2);
+        query.page = 5;
+        assert!(query.run(&songs, &me).charts.is_empty());
+
+        query.sort = ScoreSort::Level;
+        query.descending = false;
+        query.per_page = None;
+        let levels: Vec<_> = query
+            .run(&songs, &me)
+            .charts
+            .iter()
+            .map(|c| c.level)
+            .collect();
+        assert_eq!(levels, [1, 2, 3, 4, 8, 9, 14, 15]);
+    }
+}
@@ -50,7 +50,7 @@ impl Scores {
         //     return false;
         // }
         let score_combo = &mut self[sanbai_entry.difficulty as usize];
-        let old_score_combo = score_combo.clone();
+        let old_score_combo = *score_combo;
 
         match score_combo.as_mut() {
             Some(difficulty) => {
@@ -65,11 +65,7 @@ impl Scores {
                 });
             }
         };
-        if *score_combo != old_score_combo {
-            true
-        } else {
-            false
-        }
+        *score_combo != old_score_combo
     }
 }
 
@@ -143,7 +139,7 @@ impl ScoreRow {
     /// });
     /// ```
     pub fn maximize(self, other: Self) -> Self {
-        let mut new = self.clone();
+        let mut new = self;
         new.score = std::cmp::max(self.score, other.score);
         new.lamp = std::cmp::max(self.lamp, other.lamp);
         new.time_played = std::cmp::max(self.time_played, other.time_played);
@@ -167,6 +163,18 @@ pub enum LampType {
 }
 
 impl LampType {
+    /// `true` if the chart was passed. [`LampType::Unknown`] doesn't count,
+    /// since it could be a fail
+    pub fn is_clear(self) -> bool {
+        self >= Self::NoCombo
+    }
+
+    /// `true` for any full combo, including Skill Attack's good/great combo.
+    /// A life 4 combo isn't a full combo
+    pub fn is_full_combo(self) -> bool {
+        self >= Self::GoodGreatCombo
+    }
+
     /// Converts the integer Skill Attack uses to represent their combo type
     /// into `LampType`
     pub fn from_skill_attack_index(index: u8) -> Option<Self> {
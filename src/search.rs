@@ -1,242 +1,636 @@
-use crate::ddr_song::{Chart, DDRSong};
-use crate::website_backends::skill_attack::SkillAttackIndex;
-use std::str::FromStr;
+use crate::courses::Course;
+use crate::ddr_song::{Chart, ChartClass, DDRSong};
+use crate::error::QueryError;
+use crate::filter::ChartFilter;
+use crate::website_backends::sanbai::VersionRange;
+use std::ops::RangeInclusive;
 
-#[derive(Debug, Clone, Copy)]
+/// Typo tolerant matching, for when searching exactly finds nothing
+pub mod fuzzy;
+/// Song ids, site urls and Skill Attack indices that identify a single song
+pub mod identifier;
+/// A precomputed index of the song list to search faster
+pub mod index;
+/// Queries returning every song within a level or BPM range
+pub mod list;
+/// Kana, romaji, full width and accent insensitive forms of names and queries
+pub mod normalize;
+use fuzzy::{fuzzy_confidence, FuzzyConfig};
+pub use identifier::SongIdentifier;
+pub use index::SearchIndex;
+pub use list::{ListQuery, ListResult};
+use normalize::query_search_forms;
+
+#[derive(Debug, Clone)]
 pub enum SearchQuery<'query> {
     ByTitle {
         song_title: &'query str,
         chart_and_level: ChartAndLevel,
         force_doubles: bool,
+        filters: QueryFilters,
+    },
+    /// Songs by an artist, from a query starting with `artist:`
+    ByArtist {
+        artist: &'query str,
+        chart_and_level: ChartAndLevel,
+        force_doubles: bool,
+        filters: QueryFilters,
     },
-    // no one uses search by skill attack anymore, we should probably remove it
-    BySkillAttackIndex {
-        sa_index: SkillAttackIndex,
+    /// A song id, song url or Skill Attack index given instead of a title
+    ByIdentifier {
+        identifier: SongIdentifier,
         chart_and_level: ChartAndLevel,
         force_doubles: bool,
+        filters: QueryFilters,
     },
 }
 
 impl<'query> SearchQuery<'query> {
     // we can't use `FromStr` because we want to borrow from the input string
-    // TODO Do we want to differentiate between there not being enough arguments
-    // and not being able to parse the last arguments as being difficulties?
-    // TODO use real error type. Put it in error.rs?
-    pub fn parse_query(
-        query: &'query str,
-        force_doubles: bool,
-    ) -> Result<Self, NotEnoughArguments> {
+    /// Parses a query like "max 300 esp 15" or "paranoia 14 dp ver:-extreme".
+    ///
+    /// The song title comes first, followed by any of these in any order
+    /// - a chart like `esp`, or `chart:` with a long name like `chart:expert`
+    ///   or `chart:challenge-doubles`, and/or a level from 1 to 19
+    /// - `dp` or `doubles` to search doubles charts
+    /// - `ver:` a version range like `a20+`, `-x` or `x-a3`
+    /// - `lvl:` a level range like `14-16`, `15+` or `<12`
+    /// - `bpm:` a main BPM range like `>200`, `<=150` or `150-180`
+    /// - `deleted:yes` for only deleted songs, or `deleted:no` for none
+    ///
+    /// At least one of the chart or level has to be given. The first word
+    /// is always part of the title, even if it looks like a chart or level,
+    /// and so is any number before the level, so "max 300 15" and
+    /// "pluto 2 14" search for "max 300" and "pluto 2".
+    ///
+    /// Starting the query with `artist:` searches for songs by the artist
+    /// instead, like "artist:naoki 15" or "artist: dj taka esp"
+    pub fn parse_query(query: &'query str, force_doubles: bool) -> Result<Self, QueryError> {
         /// Helper function to cut off the level arguments from the query
         fn cut_string_end<'a>(full: &'a str, end: &'a str) -> &'a str {
             let byte_offset = end.as_ptr() as usize - full.as_ptr() as usize;
             &full[..byte_offset]
         }
 
-        let mut last_two = query.split_whitespace();
-        // We don't ever want to parse the leading element as the difficulty, so we do this to skip
-        // We can't just `.split_whitespace().skip(1)` because then it won't be a
-        // `DoubleEndedIterator`
-        // This also ensures that the song title can't be empty
-        last_two.next();
-        let last_str = last_two.next_back();
-        let last = last_str
-            .map(|s| s.parse::<DifficultyOrLevel>().ok())
-            .flatten();
-        // let penultimate_str = last_two.next_back();
-        // let penultimate = penultimate_str
-        //     .map(|s| s.parse::<DifficultyOrLevel>().ok())
-        //     .flatten();
-        // use DifficultyOrLevel::*;
-        match last {
-            Some(last) => {
-                let song_title = cut_string_end(query, last_str.unwrap()).trim();
-                let mut chart_and_level: ChartAndLevel = last.into();
-                if force_doubles {
-                    chart_and_level.force_doubles();
-                }
+        let query = query.trim_start();
+        let artist_query = query
+            .get(.."artist:".len())
+            .filter(|prefix| prefix.eq_ignore_ascii_case("artist:"))
+            .map(|prefix| &query[prefix.len()..]);
+        let query = artist_query.unwrap_or(query);
+
+        let tokens: Vec<&str> = query.split_whitespace().collect();
+        if tokens.is_empty() {
+            return Err(QueryError::MissingSongTitle);
+        }
 
-                match song_title.parse::<SkillAttackIndex>() {
-                    Ok(sa_index) => Ok(Self::BySkillAttackIndex {
-                        sa_index,
-                        chart_and_level,
-                        force_doubles,
-                    }),
-                    Err(_) => Ok(Self::ByTitle {
-                        song_title,
-                        chart_and_level,
-                        force_doubles,
-                    }),
+        let mut force_doubles = force_doubles;
+        let mut chart: Option<Chart> = None;
+        let mut level: Option<u8> = None;
+        let mut filters = QueryFilters::default();
+        // Going backwards from the end, so when something is given twice the
+        // last one wins
+        let mut title_end = tokens.len();
+        while title_end > 1 {
+            let token = match QueryToken::parse(tokens[title_end - 1])? {
+                Some(token) => token,
+                None => break,
+            };
+            match token {
+                QueryToken::Chart(c) => match chart {
+                    Some(later) => return Err(QueryError::TooManyCharts(c, later)),
+                    None => chart = Some(c),
+                },
+                // Only the last number is the level, the ones before it
+                // end the title like "MAX 300"
+                QueryToken::Level(_) if level.is_some() => break,
+                QueryToken::Level(l) => level = Some(l),
+                QueryToken::Doubles => force_doubles = true,
+                QueryToken::Levels(levels) => {
+                    filters.levels.get_or_insert(levels);
+                }
+                QueryToken::Versions(versions) => {
+                    filters.versions.get_or_insert(versions);
+                }
+                QueryToken::Bpms(bpms) => {
+                    filters.bpms.get_or_insert(bpms);
+                }
+                QueryToken::Deleted(deleted) => {
+                    filters.deleted.get_or_insert(deleted);
                 }
             }
-            _ => Err(NotEnoughArguments),
-        }
-        // match (last, penultimate) {
-        //     // Either only last parsed, or last and penultimate parsed to the same variant type
-        //     // ignore penultimate and just use last
-        //     (Some(last), None)
-        //     | (Some(last @ Difficulty(_)), Some(Difficulty(_)))
-        //     | (Some(last @ Level(_)), Some(Level(_))) => {
-        //         let song_title = cut_string_end(query, last_str.unwrap()).trim();
-        //         let mut chart_and_level: ChartAndLevel = last.into();
-        //         if force_doubles {
-        //             chart_and_level.force_doubles();
-        //         }
-
-        //         match song_title.parse::<SkillAttackIndex>() {
-        //             Ok(sa_index) => Ok(Self::BySkillAttackIndex {
-        //                 sa_index,
-        //                 chart_and_level,
-        //                 force_doubles,
-        //             }),
-        //             Err(_) => Ok(Self::ByTitle {
-        //                 song_title,
-        //                 chart_and_level,
-        //                 force_doubles,
-        //             }),
-        //         }
-        //     }
-        //     // last and penultimate parsed to different variants, incorporate both and cut
-        //     // at where penultimate started
-        //     (Some(Difficulty(c)), Some(Level(l))) | (Some(Level(l)), Some(Difficulty(c))) => {
-        //         let song_title = cut_string_end(query, penultimate_str.unwrap()).trim();
-        //         let mut chart_and_level: ChartAndLevel = (c, l).into();
-        //         if force_doubles {
-        //             chart_and_level.force_doubles();
-        //         }
-        //         match song_title.parse::<SkillAttackIndex>() {
-        //             Ok(sa_index) => Ok(Self::BySkillAttackIndex {
-        //                 sa_index,
-        //                 chart_and_level,
-        //                 force_doubles,
-        //             }),
-        //             Err(_) => Ok(Self::ByTitle {
-        //                 song_title,
-        //                 chart_and_level,
-        //                 force_doubles,
-        //             }),
-        //         }
-        //     }
-        //     // either neither parsed, or not enough arguments given
-        //     _ => Err(NotEnoughArguments),
-        // }
+            title_end -= 1;
+        }
+
+        let song_title = match tokens.get(title_end) {
+            Some(first_modifier) => cut_string_end(query, first_modifier).trim(),
+            None => query.trim(),
+        };
+        let mut chart_and_level = match (chart, level) {
+            (Some(c), Some(l)) => ChartAndLevel::Both(c, l),
+            (Some(c), None) => ChartAndLevel::Chart(c),
+            (None, Some(l)) => ChartAndLevel::Level(l),
+            (None, None) => return Err(QueryError::MissingChartOrLevel),
+        };
+        if force_doubles {
+            chart_and_level.force_doubles();
+        }
+
+        if let Some(artist) = artist_query.map(|_| song_title) {
+            return Ok(Self::ByArtist {
+                artist,
+                chart_and_level,
+                force_doubles,
+                filters,
+            });
+        }
+        match song_title.parse::<SongIdentifier>() {
+            Ok(identifier) => Ok(Self::ByIdentifier {
+                identifier,
+                chart_and_level,
+                force_doubles,
+                filters,
+            }),
+            Err(_) => Ok(Self::ByTitle {
+                song_title,
+                chart_and_level,
+                force_doubles,
+                filters,
+            }),
+        }
     }
 
+    /// Finds the best matching song, see [`SearchQuery::search_ranked`]
     pub fn search<'ddr_song>(
         &self,
         song_list: impl IntoIterator<Item = &'ddr_song DDRSong>,
     ) -> Option<SearchResult<'ddr_song>> {
-        match *self {
+        self.search_ranked(song_list)
+            .into_iter()
+            .next()
+            .map(|ranked| ranked.result)
+    }
+
+    /// Finds every song matching the query, best match first, with the
+    /// default typo tolerance. See [`SearchQuery::search_ranked_with`]
+    pub fn search_ranked<'ddr_song>(
+        &self,
+        song_list: impl IntoIterator<Item = &'ddr_song DDRSong>,
+    ) -> Vec<RankedSearchResult<'ddr_song>> {
+        self.search_ranked_with(song_list, &FuzzyConfig::default())
+    }
+
+    /// Finds every song matching the query, best match first.
+    ///
+    /// Each song is scored by its best matching name, see [`match_confidence`].
+    /// If no song contains every word of the query, the search is retried
+    /// allowing for typos, see [`fuzzy_confidence`].
+    /// Songs with the same confidence stay in the order of `song_list`
+    pub fn search_ranked_with<'ddr_song>(
+        &self,
+        song_list: impl IntoIterator<Item = &'ddr_song DDRSong>,
+        fuzzy_config: &FuzzyConfig,
+    ) -> Vec<RankedSearchResult<'ddr_song>> {
+        let songs: Vec<&DDRSong> = song_list.into_iter().collect();
+        match self {
             SearchQuery::ByTitle {
-                song_title,
-                chart_and_level,
-                force_doubles,
-            } => {
-                let (search_challenge, search_level) = match chart_and_level {
-                    ChartAndLevel::Level(l) => (None, Some(l)),
-                    ChartAndLevel::Chart(c) => (Some(c == Chart::CSP || c == Chart::CDP), None),
-                    ChartAndLevel::Both(c, l) => {
-                        (Some(c == Chart::CSP || c == Chart::CDP), Some(l))
+                song_title: name, ..
+            }
+            | SearchQuery::ByArtist { artist: name, .. } => {
+                let queries = query_search_forms(name);
+                self.rank_by_name(&queries, &songs, || songs.clone(), fuzzy_config)
+            }
+            SearchQuery::ByIdentifier { identifier, .. } => {
+                let song = songs.into_iter().find(|song| match identifier {
+                    SongIdentifier::SongId(song_id) => song.song_id == *song_id,
+                    SongIdentifier::SkillAttackIndex(index) => {
+                        song.skill_attack_index == Some(*index)
                     }
+                });
+                self.identified_result(song)
+            }
+        }
+    }
+
+    /// Same as [`SearchQuery::search_ranked_with`], but only scores the songs
+    /// the index says could match instead of every song.
+    ///
+    /// `index` has to have been built from `song_list`
+    pub fn search_indexed<'ddr_song>(
+        &self,
+        index: &SearchIndex,
+        song_list: &'ddr_song [DDRSong],
+        fuzzy_config: &FuzzyConfig,
+    ) -> Vec<RankedSearchResult<'ddr_song>> {
+        match self {
+            SearchQuery::ByTitle { song_title, .. } => {
+                let queries = query_search_forms(song_title);
+                let candidates: Vec<&DDRSong> = match index.candidates(&queries) {
+                    Some(indices) => indices.into_iter().map(|i| &song_list[i]).collect(),
+                    None => song_list.iter().collect(),
                 };
-                let query = song_title.to_lowercase();
-                let mut fuzzy_match_candidates = vec![];
-
-                for song in song_list
-                    .into_iter()
-                    .filter(|song| {
-                        // challenge filter
-                        match search_challenge {
-                            Some(true) => song.ratings.has_challenge_chart(),
-                            Some(false) => song.ratings.has_non_challenge_charts(),
-                            None => true, // no info so can't filter filter
-                        }
-                    })
-                    .filter(|song| {
-                        // level filter
-                        match search_level {
-                            Some(l) => {
-                                if force_doubles || chart_and_level.is_doubles_chart() {
-                                    song.ratings.contains_doubles(l)
-                                } else {
-                                    song.ratings.contains_single(l)
-                                }
-                            }
-                            None => true, // no info so can't filter
-                        }
+                // A typo can break every trigram of a short word, so the
+                // fallback still looks at every song
+                self.rank_by_name(
+                    &queries,
+                    &candidates,
+                    || song_list.iter().collect(),
+                    fuzzy_config,
+                )
+            }
+            // The index only has song names, so every song is an artist candidate
+            SearchQuery::ByArtist { artist, .. } => {
+                let queries = query_search_forms(artist);
+                let songs: Vec<&DDRSong> = song_list.iter().collect();
+                self.rank_by_name(&queries, &songs, || songs.clone(), fuzzy_config)
+            }
+            SearchQuery::ByIdentifier { identifier, .. } => {
+                self.identified_result(index.position(identifier).map(|i| &song_list[i]))
+            }
+        }
+    }
+
+    /// Searches like [`SearchQuery::search_indexed`], but explains what went
+    /// wrong when nothing is found: whether the song exists but not the
+    /// chart, or there is no such song at all. Songs tied for the best match
+    /// are returned together instead of just picking the first
+    pub fn search_outcome<'ddr_song>(
+        &self,
+        index: &SearchIndex,
+        song_list: &'ddr_song [DDRSong],
+        fuzzy_config: &FuzzyConfig,
+    ) -> SearchOutcome<'ddr_song> {
+        let mut ranked = self.search_indexed(index, song_list, fuzzy_config);
+        if let Some(best) = ranked.first().copied() {
+            let num_tied = ranked
+                .iter()
+                .take_while(|r| r.confidence == best.confidence)
+                .count();
+            if num_tied > 1 {
+                ranked.truncate(num_tied);
+                return SearchOutcome::Ambiguous(ranked);
+            }
+            ranked.remove(0);
+            return SearchOutcome::Found {
+                best,
+                others: ranked,
+            };
+        }
+
+        // Nothing has the chart, so look for the song by itself
+        let with_available = |song: &'ddr_song DDRSong, confidence: f32| {
+            let available = self.available_charts(song);
+            (!available.is_empty()).then_some((song, confidence, available))
+        };
+        let found = match self {
+            SearchQuery::ByIdentifier { identifier, .. } => index
+                .position(identifier)
+                .and_then(|i| with_available(&song_list[i], 1.0)),
+            SearchQuery::ByTitle {
+                song_title: name, ..
+            }
+            | SearchQuery::ByArtist { artist: name, .. } => {
+                let queries = query_search_forms(name);
+                let best =
+                    |songs: &mut dyn Iterator<Item = &'ddr_song DDRSong>,
+                     confidence: &dyn Fn(&str, &str) -> Option<f32>| {
+                        songs
+                            .filter_map(|song| {
+                                with_available(
+                                    song,
+                                    best_name_confidence(
+                                        self.searched_names(song),
+                                        &queries,
+                                        confidence,
+                                    )?,
+                                )
+                            })
+                            // The first of equally good matches, like the ranking
+                            .reduce(|best, next| if next.1 > best.1 { next } else { best })
+                    };
+                let candidates = match self {
+                    SearchQuery::ByTitle { .. } => index.candidates(&queries),
+                    _ => None,
+                };
+                let mut candidates: Box<dyn Iterator<Item = _>> = match &candidates {
+                    Some(indices) => Box::new(indices.iter().map(|&i| &song_list[i])),
+                    None => Box::new(song_list.iter()),
+                };
+                best(&mut candidates, &match_confidence).or_else(|| {
+                    best(&mut song_list.iter(), &|name, query| {
+                        fuzzy_confidence(name, query, fuzzy_config)
                     })
-                {
-                    // fuzzy match over each name/nickname
-                    'next_name: for search_name in &song.search_names {
-                        // exact match, return right away
-                        // This makes it so searching "bi" matches the right song
-                        if search_name == &query {
-                            return SearchResult::new(song, chart_and_level, force_doubles);
-                        }
-                        // check if we match this given search name, splitting
-                        // and removing the part we matched so the next word doesn't
-                        // accidentally match the same part
-                        let mut search_name_parts = vec![search_name.as_str()];
-                        for query_word in query.split_whitespace() {
-                            match search_name_parts.iter().enumerate().find_map(|(i, s)| {
-                                match s.find(query_word) {
-                                    Some(cutoff) => Some((
-                                        i,
-                                        s[..cutoff].trim(),
-                                        s[cutoff + query_word.len()..].trim(),
-                                    )),
-                                    None => None,
-                                }
-                            }) {
-                                Some((i, left, right)) => {
-                                    // println!("Before {:?}", search_name_parts);
-                                    search_name_parts.remove(i);
-                                    if !right.is_empty() {
-                                        search_name_parts.insert(i, right);
-                                    }
-                                    if !left.is_empty() {
-                                        search_name_parts.insert(i, left);
-                                    }
-                                    // println!("After  {:?}", search_name_parts);
-                                }
-                                None => continue 'next_name,
-                            }
-                        }
-
-                        fuzzy_match_candidates.push((song, search_name_parts))
-                    }
-                }
-                // for (song, remaining_search_part) in &fuzzy_match_candidates {
-                //     println!("{} {:?}", song.song_name, remaining_search_part);
-                // }
-                // TODO use some better heuristics to choose the song if multiple songs match
-                // current: Whatever was first alphabetically
-                // This has trouble with "roppongi d", which matches on all the roppongi evolved
-                // chart and chooses "roppongi evolved ver.a"
-                // Alternative solution, get the "song patch" working and just put in search names
-                // for the roppongis without the "evolved"
-                fuzzy_match_candidates
-                    .get(0)
-                    .and_then(|(song, _)| SearchResult::new(song, chart_and_level, force_doubles))
+                })
             }
-            SearchQuery::BySkillAttackIndex {
-                sa_index,
+        };
+        match found {
+            Some((song, confidence, available)) => SearchOutcome::ChartMissing {
+                song,
+                confidence,
+                available,
+            },
+            None => SearchOutcome::NotFound,
+        }
+    }
+
+    /// The charts of the song in the searched play style that pass the
+    /// filters of the query
+    fn available_charts(&self, song: &DDRSong) -> Vec<(Chart, u8)> {
+        let (chart_and_level, force_doubles, filters) = self.chart_options();
+        let doubles = force_doubles || chart_and_level.is_doubles_chart();
+        song.ratings
+            .charts()
+            .filter(|&(chart, level)| {
+                chart.is_doubles() == doubles && filters.matches(song, chart, level)
+            })
+            .collect()
+    }
+
+    /// The chart or level, whether to search doubles, and the filters, which
+    /// every kind of query has
+    fn chart_options(&self) -> (ChartAndLevel, bool, &QueryFilters) {
+        match self {
+            SearchQuery::ByTitle {
                 chart_and_level,
                 force_doubles,
-            } => {
-                // No need to filter, we are just looking for a specific index
-                for song in song_list {
-                    if song.skill_attack_index == Some(sa_index) {
-                        // sanity check
-                        return SearchResult::new(song, chart_and_level, force_doubles);
+                filters,
+                ..
+            }
+            | SearchQuery::ByArtist {
+                chart_and_level,
+                force_doubles,
+                filters,
+                ..
+            }
+            | SearchQuery::ByIdentifier {
+                chart_and_level,
+                force_doubles,
+                filters,
+                ..
+            } => (*chart_and_level, *force_doubles, filters),
+        }
+    }
+
+    /// The names of the song the query is matched against, the song names or
+    /// the artist names
+    fn searched_names<'a>(&self, song: &'a DDRSong) -> &'a [String] {
+        match self {
+            SearchQuery::ByArtist { .. } => &song.artist_search_names,
+            _ => &song.search_names,
+        }
+    }
+
+    /// Scores `candidates` against every form of the title or artist, falling
+    /// back to scoring `fuzzy_candidates` allowing for typos if nothing matches
+    fn rank_by_name<'ddr_song>(
+        &self,
+        queries: &[String],
+        candidates: &[&'ddr_song DDRSong],
+        fuzzy_candidates: impl FnOnce() -> Vec<&'ddr_song DDRSong>,
+        fuzzy_config: &FuzzyConfig,
+    ) -> Vec<RankedSearchResult<'ddr_song>> {
+        if let SearchQuery::ByIdentifier { .. } = self {
+            return vec![];
+        }
+        let (chart_and_level, force_doubles, filters) = self.chart_options();
+        let (search_challenge, search_level) = match chart_and_level {
+            ChartAndLevel::Level(l) => (None, Some(l)),
+            ChartAndLevel::Chart(c) => (Some(c.class() == ChartClass::Challenge), None),
+            ChartAndLevel::Both(c, l) => (Some(c.class() == ChartClass::Challenge), Some(l)),
+        };
+        let could_match = |song: &DDRSong| {
+            // challenge filter
+            let challenge_ok = match search_challenge {
+                Some(true) => song.ratings.has_challenge_chart(),
+                Some(false) => song.ratings.has_non_challenge_charts(),
+                None => true, // no info so can't filter filter
+            };
+            // level filter
+            let level_ok = match search_level {
+                Some(l) => {
+                    if force_doubles || chart_and_level.is_doubles_chart() {
+                        song.ratings.contains_doubles(l)
+                    } else {
+                        song.ratings.contains_single(l)
+                    }
+                }
+                None => true, // no info so can't filter
+            };
+            challenge_ok && level_ok
+        };
+
+        let rank = |songs: &[&'ddr_song DDRSong],
+                    confidence: &dyn Fn(&str, &str) -> Option<f32>| {
+            let mut ranked = vec![];
+            for &song in songs.iter().filter(|song| could_match(song)) {
+                if let Some(confidence) =
+                    best_name_confidence(self.searched_names(song), queries, confidence)
+                {
+                    let result = SearchResult::new(song, chart_and_level, force_doubles)
+                        .filter(|r| filters.matches(r.song, r.chart, r.level));
+                    if let Some(result) = result {
+                        ranked.push(RankedSearchResult { result, confidence });
                     }
                 }
-                // Couldn't find matching skill attack index
-                None
             }
+            ranked
+        };
+        let mut ranked = rank(candidates, &match_confidence);
+        if ranked.is_empty() {
+            ranked = rank(&fuzzy_candidates(), &|name, query| {
+                fuzzy_confidence(name, query, fuzzy_config)
+            });
         }
+        // Stable, so equally good matches stay in song list order
+        ranked.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+        ranked
+    }
+
+    /// The result of an identifier query once the song is found. There is
+    /// nothing to rank, so it is either a certain match or nothing
+    fn identified_result<'ddr_song>(
+        &self,
+        song: Option<&'ddr_song DDRSong>,
+    ) -> Vec<RankedSearchResult<'ddr_song>> {
+        let (chart_and_level, force_doubles, filters) = match self {
+            SearchQuery::ByIdentifier { .. } => self.chart_options(),
+            _ => return vec![],
+        };
+        song.and_then(|song| SearchResult::new(song, chart_and_level, force_doubles))
+            .filter(|r| filters.matches(r.song, r.chart, r.level))
+            .map(|result| RankedSearchResult {
+                result,
+                confidence: 1.0,
+            })
+            .into_iter()
+            .collect()
     }
 }
 
+/// What a search found, see [`SearchQuery::search_outcome`]
+#[derive(Debug, Clone)]
+pub enum SearchOutcome<'ddr_song> {
+    /// One song matched best, along with the worse matches best first
+    Found {
+        best: RankedSearchResult<'ddr_song>,
+        others: Vec<RankedSearchResult<'ddr_song>>,
+    },
+    /// The song was found, but it doesn't have the chart or level asked for.
+    /// `available` has the charts it does have of the searched play style
+    ChartMissing {
+        song: &'ddr_song DDRSong,
+        confidence: f32,
+        available: Vec<(Chart, u8)>,
+    },
+    /// Several songs matched equally well, in song list order
+    Ambiguous(Vec<RankedSearchResult<'ddr_song>>),
+    NotFound,
+}
+
+/// A search result along with how confident we are that it is the song
+/// that was searched for
+#[derive(Debug, Copy, Clone)]
+pub struct RankedSearchResult<'ddr_song> {
+    pub result: SearchResult<'ddr_song>,
+    /// 1.0 for an exact match, and between 0.0 and 1.0 otherwise
+    pub confidence: f32,
+}
+
+/// The best confidence of any of the names against any form of the query
+fn best_name_confidence(
+    names: &[String],
+    queries: &[String],
+    confidence: &dyn Fn(&str, &str) -> Option<f32>,
+) -> Option<f32> {
+    names
+        .iter()
+        .flat_map(|name| queries.iter().filter_map(|query| confidence(name, query)))
+        .max_by(f32::total_cmp)
+}
+
+/// How well `query` matches `search_name`, both lowercase. `None` if not
+/// every word of the query is in the name.
+///
+/// An exact match is 1.0. Otherwise each query word scores by how it is
+/// found in the name: as a whole word, as the start of a word, or anywhere
+/// inside a word. That is combined with how much of the name the query
+/// covers, so shorter names with less left over score higher. This is what
+/// makes "roppongi d" match "roppongi evolved ver.d" over "ver.a", even
+/// though the "d" in "evolved" matches every version
+pub fn match_confidence(search_name: &str, query: &str) -> Option<f32> {
+    if search_name == query {
+        return Some(1.0);
+    }
+    let leftover = match_query_words(search_name, query)?;
+
+    let words: Vec<_> = query.split_whitespace().collect();
+    if words.is_empty() {
+        return None;
+    }
+    let word_score = words
+        .iter()
+        .map(|word| word_match_score(search_name, word))
+        .sum::<f32>()
+        / words.len() as f32;
+
+    let name_len = search_name.chars().filter(|c| !c.is_whitespace()).count();
+    let leftover_len: usize = leftover
+        .iter()
+        .map(|part| part.chars().filter(|c| !c.is_whitespace()).count())
+        .sum();
+    let coverage = 1.0 - leftover_len as f32 / name_len.max(1) as f32;
+
+    // Keep every inexact match below an exact one
+    Some(0.95 * (0.7 * word_score + 0.3 * coverage))
+}
+
+/// 1.0 if `word` is a whole word in `search_name`, 0.7 if it starts a word,
+/// 0.4 if it is only inside a word
+fn word_match_score(search_name: &str, word: &str) -> f32 {
+    let is_boundary = |c: Option<char>| c.is_none_or(|c| !c.is_alphanumeric());
+    search_name
+        .match_indices(word)
+        .map(|(i, _)| {
+            let before = search_name[..i].chars().next_back();
+            let after = search_name[i + word.len()..].chars().next();
+            match (is_boundary(before), is_boundary(after)) {
+                (true, true) => 1.0,
+                (true, false) => 0.7,
+                _ => 0.4,
+            }
+        })
+        .fold(0.0, f32::max)
+}
+
+/// Checks if every word of `query` is in `search_name`, returning the parts of
+/// `search_name` that weren't matched by any word.
+///
+/// Each matched part is split off and removed so the next word doesn't
+/// accidentally match the same part
+fn match_query_words<'a>(search_name: &'a str, query: &str) -> Option<Vec<&'a str>> {
+    let mut search_name_parts = vec![search_name];
+    for query_word in query.split_whitespace() {
+        let (i, left, right) = search_name_parts.iter().enumerate().find_map(|(i, s)| {
+            s.find(query_word)
+                .map(|cutoff| (i, s[..cutoff].trim(), s[cutoff + query_word.len()..].trim()))
+        })?;
+        search_name_parts.remove(i);
+        if !right.is_empty() {
+            search_name_parts.insert(i, right);
+        }
+        if !left.is_empty() {
+            search_name_parts.insert(i, left);
+        }
+    }
+    Some(search_name_parts)
+}
+
+/// Finds every course matching the query, best match first, using the same
+/// rules as song search: exact word matches first, falling back to allowing
+/// typos if no course contains every word.
+///
+/// Works the same for course trials and dan courses, e.g. "8th dan" or "初段"
+pub fn search_courses<'course>(
+    query: &str,
+    courses: impl IntoIterator<Item = &'course Course>,
+    fuzzy_config: &FuzzyConfig,
+) -> Vec<RankedCourseResult<'course>> {
+    let queries = query_search_forms(query);
+    if queries[0].trim().is_empty() {
+        return vec![];
+    }
+    let courses: Vec<&Course> = courses.into_iter().collect();
+    let rank = |confidence: &dyn Fn(&str, &str) -> Option<f32>| {
+        let mut ranked: Vec<_> = courses
+            .iter()
+            .filter_map(|&course| {
+                course
+                    .search_names
+                    .iter()
+                    .flat_map(|name| queries.iter().filter_map(|q| confidence(name, q)))
+                    .max_by(f32::total_cmp)
+                    .map(|confidence| RankedCourseResult { course, confidence })
+            })
+            .collect();
+        ranked.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+        ranked
+    };
+    let ranked = rank(&match_confidence);
+    if !ranked.is_empty() {
+        return ranked;
+    }
+    rank(&|name, query| fuzzy_confidence(name, query, fuzzy_config))
+}
+
+/// A course found by [`search_courses`], along with how confident we are that
+/// it is the course that was searched for
+#[derive(Debug, Copy, Clone)]
+pub struct RankedCourseResult<'course> {
+    pub course: &'course Course,
+    /// 1.0 for an exact match, and between 0.0 and 1.0 otherwise
+    pub confidence: f32,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct SearchResult<'ddr_song> {
     pub song: &'ddr_song DDRSong,
@@ -270,12 +664,34 @@ impl<'ddr_song> SearchResult<'ddr_song> {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct NotEnoughArguments;
+/// Filters given in a search query, see [`SearchQuery::parse_query`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryFilters {
+    pub levels: Option<RangeInclusive<u8>>,
+    pub versions: Option<VersionRange>,
+    /// Main BPM of the song
+    pub bpms: Option<RangeInclusive<u16>>,
+    /// `Some(true)` for only deleted songs, `Some(false)` for no deleted songs,
+    /// and `None` for either
+    pub deleted: Option<bool>,
+}
+
+impl QueryFilters {
+    pub fn matches(&self, song: &DDRSong, chart: Chart, level: u8) -> bool {
+        let chart_filter = ChartFilter {
+            levels: self.levels.clone(),
+            versions: self.versions,
+            bpms: self.bpms.clone(),
+            include_deleted: self.deleted != Some(false),
+            ..Default::default()
+        };
+        chart_filter.matches(song, chart, level) && (self.deleted != Some(true) || song.deleted)
+    }
+}
 
 /// Represents the different possibilities of parsing the search query,
 /// either having only level information, chart information, or both
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ChartAndLevel {
     /// The level of the song, from 1-19
     Level(u8),
@@ -296,65 +712,649 @@ impl ChartAndLevel {
     fn force_doubles(&mut self) {
         match self {
             ChartAndLevel::Chart(c) | ChartAndLevel::Both(c, _) => {
-                *c = match c {
-                    Chart::GSP | Chart::BSP => Chart::BDP,
-                    Chart::DSP => Chart::DDP,
-                    Chart::ESP => Chart::EDP,
-                    Chart::CSP => Chart::CDP,
-                    Chart::BDP | Chart::DDP | Chart::EDP | Chart::CDP => *c,
-                };
+                // There is no beginner doubles, so basic doubles is the closest
+                *c = c.to_doubles().unwrap_or(Chart::BDP);
             }
             _ => {}
         }
     }
 }
 
-impl From<DifficultyOrLevel> for ChartAndLevel {
-    fn from(d_or_l: DifficultyOrLevel) -> Self {
-        match d_or_l {
-            DifficultyOrLevel::Difficulty(c) => Self::Chart(c),
-            DifficultyOrLevel::Level(l) => Self::Level(l),
-        }
-    }
-}
-
 impl From<(Chart, u8)> for ChartAndLevel {
     fn from((c, l): (Chart, u8)) -> Self {
         Self::Both(c, l)
     }
 }
 
-/// Internal type to help with parsing
-#[derive(Debug, Copy, Clone)]
-enum DifficultyOrLevel {
-    Difficulty(Chart),
+/// Internal type to help with parsing, a single word after the song title
+#[derive(Debug, Clone)]
+enum QueryToken {
+    Chart(Chart),
     Level(u8),
+    Doubles,
+    Levels(RangeInclusive<u8>),
+    Versions(VersionRange),
+    Bpms(RangeInclusive<u16>),
+    Deleted(bool),
 }
 
-impl FromStr for DifficultyOrLevel {
-    type Err = ();
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        use Chart::*;
-        use DifficultyOrLevel::*;
-        match s {
-            "gsp" | "GSP" | "Gsp" | "bSP" => Ok(Difficulty(GSP)),
-            "bsp" | "BSP" | "Bsp" => Ok(Difficulty(BSP)),
-            "dsp" | "DSP" | "Dsp" => Ok(Difficulty(DSP)),
-            "esp" | "ESP" | "Esp" => Ok(Difficulty(ESP)),
-            "csp" | "CSP" | "Csp" => Ok(Difficulty(CSP)),
-            "bdp" | "BDP" | "Bdp" => Ok(Difficulty(BDP)),
-            "ddp" | "DDP" | "Ddp" => Ok(Difficulty(DDP)),
-            "edp" | "EDP" | "Edp" => Ok(Difficulty(EDP)),
-            "cdp" | "CDP" | "Cdp" => Ok(Difficulty(CDP)),
-            _ => {
-                if let Ok(level) = s.parse::<u8>() {
-                    if 0 < level && level < 20 {
-                        return Ok(Level(level));
-                    }
+impl QueryToken {
+    /// `Ok(None)` if the word is part of the song title
+    fn parse(s: &str) -> Result<Option<Self>, QueryError> {
+        let invalid = |reason: String| QueryError::InvalidFilter {
+            filter: s.to_string(),
+            reason,
+        };
+
+        if let Some((key, value)) = s.split_once(':') {
+            let token = match key.to_lowercase().as_str() {
+                "chart" => Self::Chart(value.parse().map_err(|e| invalid(format!("{}", e)))?),
+                "ver" | "version" => {
+                    Self::Versions(value.parse().map_err(|e| invalid(format!("{}", e)))?)
+                }
+                "lvl" | "level" => {
+                    // Within 1-19, so the levels always fit in a u8
+                    let range = parse_range(value, 1..=19).map_err(invalid)?;
+                    Self::Levels(*range.start() as u8..=*range.end() as u8)
                 }
-                Err(())
+                "bpm" => Self::Bpms(parse_range(value, 0..=u16::MAX).map_err(invalid)?),
+                "deleted" => match value.to_lowercase().as_str() {
+                    "yes" | "y" | "true" | "only" => Self::Deleted(true),
+                    "no" | "n" | "false" => Self::Deleted(false),
+                    _ => return Err(invalid("expected yes or no".to_string())),
+                },
+                // Something like "Re:Born" in the title
+                _ => return Ok(None),
+            };
+            return Ok(Some(token));
+        }
+
+        let lower = s.to_lowercase();
+        if lower == "dp" || lower == "doubles" {
+            return Ok(Some(Self::Doubles));
+        }
+        // Long names like "light" or "expert" are common title words, so
+        // they need `chart:`
+        if let Some(chart) = Chart::from_short_name(s) {
+            return Ok(Some(Self::Chart(chart)));
+        }
+        if let Ok(level) = s.parse::<u8>() {
+            if 0 < level && level < 20 {
+                return Ok(Some(Self::Level(level)));
             }
         }
+        Ok(None)
+    }
+}
+
+/// Parses ranges like "14-16", "15+", "-16", "15", ">200", "<=150". Open
+/// ends go to the ends of `bounds`, and anything outside of it is an error
+fn parse_range(s: &str, bounds: RangeInclusive<u16>) -> Result<RangeInclusive<u16>, String> {
+    let (min, max) = bounds.into_inner();
+    let num = |n: &str| {
+        n.trim()
+            .parse::<u16>()
+            .map_err(|_| format!("`{}` isn't a number", n))
+    };
+    let range = if let Some(n) = s.strip_prefix(">=") {
+        num(n)?..=max
+    } else if let Some(n) = s.strip_prefix("<=") {
+        min..=num(n)?
+    } else if let Some(n) = s.strip_prefix('>') {
+        num(n)?.saturating_add(1)..=max
+    } else if let Some(n) = s.strip_prefix('<') {
+        match num(n)?.checked_sub(1) {
+            Some(end) => min..=end,
+            None => return Err(format!("nothing is below {}", min)),
+        }
+    } else if let Some(n) = s.strip_suffix('+') {
+        num(n)?..=max
+    } else if let Some((start, end)) = s.split_once('-') {
+        let start = if start.is_empty() { min } else { num(start)? };
+        let end = if end.is_empty() { max } else { num(end)? };
+        start..=end
+    } else {
+        let n = num(s)?;
+        n..=n
+    };
+    if range.is_empty() {
+        return Err(format!("`{}` doesn't contain anything", s));
+    }
+    if *range.start() < min || *range.end() > max {
+        return Err(format!("`{}` isn't within {}-{}", s, min, max));
+    }
+    Ok(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{song, song_id};
+
+    #[test]
+    fn ranked_search() {
+        let ratings = [3, 7, 12, 15, 0, 7, 12, 15, 0];
+        let songs = [
+            song(&song_id(1), "ROPPONGI EVOLVED ver.A", ratings)
+                .artist("TAG")
+                .build(),
+            song(&song_id(2), "ROPPONGI EVOLVED ver.B", ratings)
+                .artist("TAG")
+                .build(),
+            song(&song_id(3), "ROPPONGI EVOLVED ver.C", ratings)
+                .artist("TAG")
+                .build(),
+            song(&song_id(4), "ROPPONGI EVOLVED ver.D", ratings)
+                .artist("TAG")
+                .build(),
+            song(&song_id(5), "bi", ratings).artist("TAG").build(),
+            song(&song_id(6), "Bit Of Gold", ratings)
+                .artist("TAG")
+                .build(),
+        ];
+
+        let query = SearchQuery::parse_query("roppongi d esp", false).unwrap();
+        let ranked = query.search_ranked(&songs);
+        assert_eq!(ranked.len(), 4);
+        assert_eq!(ranked[0].result.song.song_name, "ROPPONGI EVOLVED ver.D");
+        assert!(ranked[0].confidence > ranked[1].confidence);
+        assert_eq!(ranked[0].result.chart, Chart::ESP);
+
+        let query = SearchQuery::parse_query("bi 15", false).unwrap();
+        let ranked = query.search_ranked(&songs);
+        assert_eq!(ranked[0].result.song.song_name, "bi");
+        assert_eq!(ranked[0].confidence, 1.0);
+        assert_eq!(ranked[1].result.song.song_name, "Bit Of Gold");
+
+        let query = SearchQuery::parse_query("gold 15", false).unwrap();
+        assert_eq!(query.search(&songs).unwrap().song.song_name, "Bit Of Gold");
+        let query = SearchQuery::parse_query("silver 15", false).unwrap();
+        assert!(query.search(&songs).is_none());
+    }
+
+    fn parse(query: &str) -> Result<(&str, ChartAndLevel, bool, QueryFilters), QueryError> {
+        match SearchQuery::parse_query(query, false)? {
+            SearchQuery::ByTitle {
+                song_title,
+                chart_and_level,
+                force_doubles,
+                filters,
+            } => Ok((song_title, chart_and_level, force_doubles, filters)),
+            q => panic!("{:?}", q),
+        }
+    }
+
+    #[test]
+    fn query_grammar() {
+        let (title, chart_and_level, doubles, filters) = parse("max 300 esp 15").unwrap();
+        assert_eq!(title, "max 300");
+        assert!(matches!(
+            chart_and_level,
+            ChartAndLevel::Both(Chart::ESP, 15)
+        ));
+        assert!(!doubles);
+        assert_eq!(filters, QueryFilters::default());
+
+        let (title, chart_and_level, doubles, _) = parse("max 300 15 esp dp").unwrap();
+        assert_eq!(title, "max 300");
+        assert!(matches!(
+            chart_and_level,
+            ChartAndLevel::Both(Chart::EDP, 15)
+        ));
+        assert!(doubles);
+
+        let (title, chart_and_level, _, filters) =
+            parse("paranoia 14 ver:a20+ lvl:14-16 bpm:>200 deleted:yes").unwrap();
+        assert_eq!(title, "paranoia");
+        assert!(matches!(chart_and_level, ChartAndLevel::Level(14)));
+        assert_eq!(
+            filters,
+            QueryFilters {
+                levels: Some(14..=16),
+                versions: Some("a20+".parse().unwrap()),
+                bpms: Some(201..=u16::MAX),
+                deleted: Some(true),
+            }
+        );
+
+        let (.., filters) = parse("paranoia 14 lvl:<12").unwrap();
+        assert_eq!(filters.levels, Some(1..=11));
+
+        // A colon in the title isn't a filter
+        let (title, ..) = parse("re:born esp").unwrap();
+        assert_eq!(title, "re:born");
+        // The first word is always the title
+        let (title, ..) = parse("esp esp").unwrap();
+        assert_eq!(title, "esp");
+        // Long chart names are title words unless given with `chart:`
+        let (title, chart_and_level, ..) = parse("the light 9").unwrap();
+        assert_eq!(title, "the light");
+        assert!(matches!(chart_and_level, ChartAndLevel::Level(9)));
+        let (title, chart_and_level, ..) = parse("the light chart:light").unwrap();
+        assert_eq!(title, "the light");
+        assert!(matches!(chart_and_level, ChartAndLevel::Chart(Chart::BSP)));
+        let (_, chart_and_level, ..) = parse("max 300 chart:expert-doubles 15").unwrap();
+        assert!(matches!(
+            chart_and_level,
+            ChartAndLevel::Both(Chart::EDP, 15)
+        ));
+        assert_eq!(
+            parse("max 300 expert"),
+            Err(QueryError::MissingChartOrLevel)
+        );
+
+        assert_eq!(parse(""), Err(QueryError::MissingSongTitle));
+        assert_eq!(parse("max 300"), Err(QueryError::MissingChartOrLevel));
+        assert_eq!(
+            parse("max 300 esp csp"),
+            Err(QueryError::TooManyCharts(Chart::ESP, Chart::CSP))
+        );
+        // Numbers before the level stay in the title
+        for (query, expected_title, expected) in [
+            ("max 300 15", "max 300", ChartAndLevel::Level(15)),
+            ("max 300 15 17", "max 300 15", ChartAndLevel::Level(17)),
+            ("pluto 2 14", "pluto 2", ChartAndLevel::Level(14)),
+            (
+                "pluto 2 esp 14",
+                "pluto 2",
+                ChartAndLevel::Both(Chart::ESP, 14),
+            ),
+            ("pluto 2 14 lvl:12+", "pluto 2", ChartAndLevel::Level(14)),
+        ] {
+            let (title, chart_and_level, ..) = parse(query).unwrap();
+            assert_eq!(
+                (title, chart_and_level),
+                (expected_title, expected),
+                "{}",
+                query
+            );
+        }
+        for bad in [
+            "chart:hard",
+            "ver:z",
+            "lvl:16-14",
+            "bpm:abc",
+            "deleted:maybe",
+            "bpm:<0",
+        ] {
+            let err = parse(&format!("max 300 esp {}", bad)).unwrap_err();
+            assert!(
+                matches!(&err, QueryError::InvalidFilter { filter, .. } if filter == bad),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn query_filters() {
+        let mut songs = [
+            song(&song_id(1), "PARANOiA", [4, 7, 10, 14, 0, 7, 10, 14, 0])
+                .artist("180")
+                .build(),
+            song(
+                &song_id(2),
+                "PARANOiA Revolution",
+                [4, 7, 12, 17, 0, 7, 12, 17, 0],
+            )
+            .artist("190")
+            .build(),
+        ];
+        songs[1].deleted = true;
+        songs[1].bpm = Some(crate::ddr_song::Bpm::Constant(190));
+
+        let names = |query: &str| -> Vec<String> {
+            SearchQuery::parse_query(query, false)
+                .unwrap()
+                .search_ranked(&songs)
+                .iter()
+                .map(|r| r.result.song.song_name.clone())
+                .collect()
+        };
+        assert_eq!(names("paranoia esp").len(), 2);
+        assert_eq!(names("paranoia esp deleted:no"), ["PARANOiA"]);
+        assert_eq!(names("paranoia esp deleted:yes"), ["PARANOiA Revolution"]);
+        assert_eq!(names("paranoia esp lvl:15+"), ["PARANOiA Revolution"]);
+        assert_eq!(names("paranoia esp bpm:>180"), ["PARANOiA Revolution"]);
+        assert!(names("paranoia esp ver:-x").is_empty());
+    }
+
+    #[test]
+    fn indexed_search() {
+        let ratings = [3, 7, 12, 15, 0, 7, 12, 15, 0];
+        let songs = [
+            song(&song_id(1), "ROPPONGI EVOLVED ver.A", ratings)
+                .artist("TAG")
+                .build(),
+            song(&song_id(2), "ROPPONGI EVOLVED ver.D", ratings)
+                .artist("TAG")
+                .build(),
+            song(&song_id(3), "bi", ratings).artist("TAG").build(),
+            song(&song_id(4), "Bit Of Gold", ratings)
+                .artist("TAG")
+                .build(),
+            song(&song_id(5), "PARANOiA", ratings).artist("180").build(),
+            song(&song_id(6), "エンジェル ドリーム", ratings)
+                .artist("TAG")
+                .build(),
+        ];
+        let index = SearchIndex::new(&songs);
+        assert_eq!(index.len(), 6);
+
+        assert_eq!(
+            index.candidates(&["roppongi d".to_string()]),
+            Some(vec![0, 1])
+        );
+        assert_eq!(index.candidates(&["gold".to_string()]), Some(vec![3]));
+        assert_eq!(index.candidates(&["bi".to_string()]), None);
+        assert_eq!(index.candidates(&["xyz".to_string()]), Some(vec![]));
+
+        for query in [
+            "roppongi d esp",
+            "bi 15",
+            "gold 15",
+            "paranoa esp",
+            "enjeru esp",
+            "えんじぇる esp",
+        ] {
+            let query = SearchQuery::parse_query(query, false).unwrap();
+            let scanned: Vec<_> = query
+                .search_ranked(&songs)
+                .iter()
+                .map(|r| (r.result.song.song_id.clone(), r.confidence))
+                .collect();
+            let indexed: Vec<_> = query
+                .search_indexed(&index, &songs, &FuzzyConfig::default())
+                .iter()
+                .map(|r| (r.result.song.song_id.clone(), r.confidence))
+                .collect();
+            assert!(!scanned.is_empty(), "{:?}", query);
+            assert_eq!(scanned, indexed, "{:?}", query);
+        }
+    }
+
+    #[test]
+    fn search_outcomes() {
+        let songs = [
+            song(&song_id(1), "PARANOiA", [3, 7, 12, 15, 0, 7, 12, 15, 0])
+                .artist("180")
+                .build(),
+            song(
+                &song_id(2),
+                "ROPPONGI EVOLVED ver.A",
+                [3, 7, 12, 15, 0, 7, 12, 15, 0],
+            )
+            .artist("TAG")
+            .build(),
+            song(
+                &song_id(3),
+                "ROPPONGI EVOLVED ver.B",
+                [3, 7, 12, 15, 0, 7, 12, 15, 0],
+            )
+            .artist("TAG")
+            .build(),
+            song(
+                &song_id(4),
+                "PARANOiA Revolution",
+                [2, 5, 10, 13, 0, 5, 10, 13, 0],
+            )
+            .artist("190")
+            .build(),
+        ];
+        let index = SearchIndex::new(&songs);
+        let outcome = |query: &str| {
+            SearchQuery::parse_query(query, false)
+                .unwrap()
+                .search_outcome(&index, &songs, &FuzzyConfig::default())
+        };
+
+        match outcome("paranoia esp") {
+            SearchOutcome::Found { best, others } => {
+                assert_eq!(best.result.song.song_name, "PARANOiA");
+                assert_eq!(others.len(), 1);
+            }
+            other => panic!("{:?}", other),
+        }
+        match outcome("roppongi 15") {
+            SearchOutcome::Ambiguous(tied) => assert_eq!(tied.len(), 2),
+            other => panic!("{:?}", other),
+        }
+        match outcome("paranoia revolution 18") {
+            SearchOutcome::ChartMissing {
+                song, available, ..
+            } => {
+                assert_eq!(song.song_name, "PARANOiA Revolution");
+                assert_eq!(
+                    available,
+                    [
+                        (Chart::GSP, 2),
+                        (Chart::BSP, 5),
+                        (Chart::DSP, 10),
+                        (Chart::ESP, 13)
+                    ]
+                );
+            }
+            other => panic!("{:?}", other),
+        }
+        // Typos still find the song when the chart is missing
+        match outcome("paranoa revolution csp") {
+            SearchOutcome::ChartMissing { song, .. } => {
+                assert_eq!(song.song_name, "PARANOiA Revolution")
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(
+            outcome("nonexistent song 15"),
+            SearchOutcome::NotFound
+        ));
+        // Filters leaving none of the charts count as not finding the song
+        assert!(matches!(
+            outcome("paranoia revolution 18 deleted:yes"),
+            SearchOutcome::NotFound
+        ));
+    }
+
+    #[test]
+    fn identifier_search() {
+        let ratings = [3, 7, 12, 15, 0, 7, 12, 15, 0];
+        let mut songs = [
+            song(&song_id(1), "PARANOiA", ratings).artist("180").build(),
+            song(&song_id(2), "MAX 300", ratings).artist("Ω").build(),
+        ];
+        songs[1].skill_attack_index = Some(405);
+        let index = SearchIndex::new(&songs);
+
+        let url = format!(
+            "https://3icecream.com/ddr/song_details/{} esp",
+            songs[0].song_id
+        );
+        for (query, name) in [(url.as_str(), "PARANOiA"), ("405 esp", "MAX 300")] {
+            let query = SearchQuery::parse_query(query, false).unwrap();
+            assert!(matches!(query, SearchQuery::ByIdentifier { .. }));
+            let found = query.search_indexed(&index, &songs, &FuzzyConfig::default());
+            assert_eq!(found.len(), 1);
+            assert_eq!(found[0].result.song.song_name, name);
+            assert_eq!(found[0].result.chart, Chart::ESP);
+            let scanned = query.search(&songs).unwrap();
+            assert_eq!(scanned.song.song_name, name);
+        }
+        let query = SearchQuery::parse_query("406 esp", false).unwrap();
+        assert!(query
+            .search_indexed(&index, &songs, &FuzzyConfig::default())
+            .is_empty());
+        // Filters apply to identified songs too
+        for (query, found) in [
+            ("405 esp lvl:15", true),
+            ("405 esp lvl:16+", false),
+            ("405 esp deleted:yes", false),
+        ] {
+            let query = SearchQuery::parse_query(query, false).unwrap();
+            let results = query.search_indexed(&index, &songs, &FuzzyConfig::default());
+            assert_eq!(results.len(), found as usize, "{:?}", query);
+            assert_eq!(query.search(&songs).is_some(), found, "{:?}", query);
+        }
+    }
+
+    #[test]
+    fn kana_and_romaji_search() {
+        let ratings = [3, 7, 12, 15, 0, 7, 12, 15, 0];
+        let songs = [
+            song(&song_id(1), "エンジェル ドリーム", ratings)
+                .artist("TAG")
+                .build(),
+            song(&song_id(2), "ＭＡＸ ３００", ratings)
+                .artist("Ω")
+                .build(),
+        ];
+        for query in ["えんじぇる esp", "エンジェル esp", "enjeru esp"] {
+            let query = SearchQuery::parse_query(query, false).unwrap();
+            let result = query.search(&songs);
+            assert_eq!(
+                result.map(|r| r.song.song_id.clone()),
+                Some(songs[0].song_id.clone()),
+                "{:?}",
+                query
+            );
+        }
+        let query = SearchQuery::parse_query("max 300 esp", false).unwrap();
+        assert_eq!(query.search_ranked(&songs)[0].confidence, 1.0);
+    }
+
+    #[test]
+    fn typo_fallback() {
+        let songs = [
+            song(&song_id(1), "PARANOiA", [4, 7, 10, 14, 0, 7, 10, 14, 0])
+                .artist("180")
+                .build(),
+            song(&song_id(2), "PARANOiA MAX", [4, 7, 11, 15, 0, 7, 11, 15, 0])
+                .artist("190")
+                .build(),
+            song(&song_id(3), "MAX 300", [3, 8, 13, 15, 17, 8, 13, 15, 17])
+                .artist("Ω")
+                .build(),
+        ];
+
+        let query = SearchQuery::parse_query("paranoa esp", false).unwrap();
+        let ranked = query.search_ranked(&songs);
+        assert_eq!(ranked.len(), 2);
+        assert!(ranked[0].confidence < 0.8);
+
+        // Matching more words wins
+        let query = SearchQuery::parse_query("paranoa max esp", false).unwrap();
+        assert_eq!(query.search(&songs).unwrap().song.song_name, "PARANOiA MAX");
+
+        let ranked = query.search_ranked_with(&songs, &FuzzyConfig::disabled());
+        assert!(ranked.is_empty());
+    }
+
+    #[test]
+    fn confidence_ordering() {
+        let whole = match_confidence("max 300", "max").unwrap();
+        let prefix = match_confidence("maxx unlimited", "max").unwrap();
+        let inside = match_confidence("climax", "max").unwrap();
+        assert!(
+            whole > prefix && prefix > inside,
+            "{whole} {prefix} {inside}"
+        );
+        assert!(whole < 1.0);
+        assert_eq!(match_confidence("max 300", "max 300"), Some(1.0));
+        assert_eq!(match_confidence("max 300", "max 400"), None);
+    }
+
+    #[test]
+    fn course_search() {
+        use crate::courses::CourseCategory;
+        use crate::ddr_song::PlayStyle;
+
+        let course = |name: &str, nicknames: &[&str]| {
+            let mut search_names = vec![];
+            for name in nicknames.iter().chain([&name]) {
+                search_names.extend(normalize::name_search_forms(name));
+            }
+            Course {
+                name: name.to_string(),
+                search_names,
+                songs: vec![None; 4],
+                play_style: PlayStyle::Singles,
+                category: CourseCategory::CourseTrial,
+                dan_level: name.parse().ok(),
+            }
+        };
+        let courses = [
+            course("1st Dan 初段", &[]),
+            course("8th Dan 八段", &[]),
+            course("18th Dan", &[]),
+            course("NAOKI Selection", &["naoki"]),
+        ];
+        let names = |query: &str| -> Vec<&str> {
+            search_courses(query, &courses, &FuzzyConfig::default())
+                .iter()
+                .map(|r| r.course.name.as_str())
+                .collect()
+        };
+        assert_eq!(names("8th dan"), ["8th Dan 八段", "18th Dan"]);
+        assert_eq!(names("初段"), ["1st Dan 初段"]);
+        assert_eq!(names("naoki"), ["NAOKI Selection"]);
+        assert_eq!(names("naoky selection"), ["NAOKI Selection"]);
+        assert!(names("kaiden").is_empty());
+        assert!(names(" ").is_empty());
+    }
+
+    #[test]
+    fn artist_search() {
+        let songs = [
+            song(&song_id(1), "MAX 300", [3, 8, 13, 15, 17, 8, 13, 15, 17])
+                .artist("Ω")
+                .build(),
+            song(&song_id(2), "PARANOiA", [4, 7, 10, 14, 0, 7, 10, 14, 0])
+                .artist("180")
+                .build(),
+            song(&song_id(3), "Pluto", [5, 8, 12, 15, 0, 0, 0, 0, 0])
+                .artist("NAOKI feat. YASPY")
+                .build(),
+            song(&song_id(4), "MAKE IT BETTER", [3, 6, 9, 12, 0, 6, 9, 12, 0])
+                .artist("mitsu-O!")
+                .build(),
+            song(
+                &song_id(5),
+                "Healing Vision",
+                [4, 7, 11, 14, 0, 7, 11, 14, 0],
+            )
+            .artist("NAOKI")
+            .build(),
+        ];
+
+        let search = |query: &str| {
+            SearchQuery::parse_query(query, false)
+                .unwrap()
+                .search_ranked(&songs)
+                .into_iter()
+                .map(|r| (r.result.song.song_name.as_str(), r.result.chart))
+                .collect::<Vec<_>>()
+        };
+        // Each artist of a collaboration matches exactly too
+        assert_eq!(
+            search("artist:NAOKI esp"),
+            [("Pluto", Chart::ESP), ("Healing Vision", Chart::ESP)]
+        );
+        assert_eq!(search("Artist: naoki 15"), [("Pluto", Chart::ESP)]);
+        // Pluto has no doubles charts so it is left out
+        assert_eq!(
+            search("artist:naoki 7 dp"),
+            [("Healing Vision", Chart::BDP)]
+        );
+        assert_eq!(search("artist:ＹＡＳＰＹ 15"), [("Pluto", Chart::ESP)]);
+        assert_eq!(
+            search("artist:mitsu-o! bsp"),
+            [("MAKE IT BETTER", Chart::BSP)]
+        );
+        assert!(search("artist:dj totto esp").is_empty());
+        // Titles aren't searched by artist queries, or artists by title queries
+        assert!(search("artist:paranoia esp").is_empty());
+        assert!(search("naoki esp").is_empty());
+        assert!(matches!(
+            SearchQuery::parse_query("artist:", false),
+            Err(QueryError::MissingSongTitle)
+        ));
     }
 }
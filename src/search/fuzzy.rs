@@ -0,0 +1,177 @@
+/// Thresholds for the typo tolerant fallback of the search, used when no
+/// song contains every word of the query exactly
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FuzzyConfig {
+    /// Query words shorter than this have to match exactly, since short words
+    /// are only a couple of typos away from almost anything
+    pub min_word_len: usize,
+    /// How many typos are allowed in a word, as a fraction of its length.
+    /// 0.25 allows one typo in words of 4 to 7 letters, two in 8 to 11, etc.
+    pub max_typo_ratio: f32,
+    /// The fraction of query words that have to match a word of the name
+    pub min_matched_words: f32,
+}
+
+impl Default for FuzzyConfig {
+    fn default() -> Self {
+        Self {
+            min_word_len: 4,
+            max_typo_ratio: 0.25,
+            min_matched_words: 0.5,
+        }
+    }
+}
+
+impl FuzzyConfig {
+    /// Never matches anything, to turn the fallback off
+    pub fn disabled() -> Self {
+        Self {
+            min_matched_words: f32::INFINITY,
+            ..Default::default()
+        }
+    }
+
+    fn max_typos(&self, word_len: usize) -> usize {
+        if word_len < self.min_word_len {
+            0
+        } else {
+            (word_len as f32 * self.max_typo_ratio) as usize
+        }
+    }
+}
+
+/// How well `query` matches `search_name` allowing for typos, both lowercase.
+/// `None` if not enough words match.
+///
+/// Each query word is compared to every word of the name, and every pair of
+/// adjacent words in case a space was left out. The confidence favours names
+/// where more of the query words match with fewer typos, and is always below
+/// 0.8 so it ranks under any exact match
+pub fn fuzzy_confidence(search_name: &str, query: &str, config: &FuzzyConfig) -> Option<f32> {
+    let query_words: Vec<Vec<char>> = query
+        .split_whitespace()
+        .map(|w| w.chars().collect())
+        .collect();
+    if query_words.is_empty() {
+        return None;
+    }
+    // Only words close enough in length to some query word can match, so
+    // skip collecting the rest. This is most of the time spent on a song list
+    let close_in_length = |len: usize| {
+        query_words
+            .iter()
+            .any(|w| len.abs_diff(w.len()) <= config.max_typos(w.len()))
+    };
+    let name_words: Vec<(&str, usize)> = search_name
+        .split_whitespace()
+        .map(|w| (w, w.chars().count()))
+        .collect();
+    let mut candidates: Vec<Vec<char>> = name_words
+        .iter()
+        .filter(|(_, len)| close_in_length(*len))
+        .map(|(w, _)| w.chars().collect())
+        .collect();
+    candidates.extend(
+        name_words
+            .windows(2)
+            .filter(|pair| close_in_length(pair[0].1 + pair[1].1))
+            .map(|pair| pair[0].0.chars().chain(pair[1].0.chars()).collect()),
+    );
+
+    let mut matched = 0;
+    let mut similarity = 0.0;
+    for word in &query_words {
+        let max_typos = config.max_typos(word.len());
+        let best = candidates
+            .iter()
+            .filter_map(|candidate| bounded_edit_distance(word, candidate, max_typos))
+            .min();
+        if let Some(distance) = best {
+            matched += 1;
+            similarity += 1.0 - distance as f32 / word.len() as f32;
+        }
+    }
+
+    let matched_fraction = matched as f32 / query_words.len() as f32;
+    if matched == 0 || matched_fraction < config.min_matched_words {
+        return None;
+    }
+    let similarity = similarity / matched as f32;
+    Some(0.8 * matched_fraction * similarity)
+}
+
+/// The optimal string alignment distance between `a` and `b`, i.e. the number
+/// of insertions, deletions, substitutions and swaps of adjacent characters
+/// to turn one into the other. `None` if it is more than `max`, which is
+/// found without filling in the whole table
+pub fn bounded_edit_distance(a: &[char], b: &[char], max: usize) -> Option<usize> {
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+    // Three rows of the table are enough for swaps
+    let mut before_prev: Vec<usize> = vec![0; b.len() + 1];
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        current[0] = i;
+        let mut row_min = current[0];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (prev[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(prev[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(before_prev[j - 2] + 1);
+            }
+            current[j] = distance;
+            row_min = row_min.min(distance);
+        }
+        if row_min > max {
+            return None;
+        }
+        std::mem::swap(&mut before_prev, &mut prev);
+        std::mem::swap(&mut prev, &mut current);
+    }
+    Some(prev[b.len()]).filter(|&d| d <= max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distance(a: &str, b: &str) -> Option<usize> {
+        let a: Vec<_> = a.chars().collect();
+        let b: Vec<_> = b.chars().collect();
+        bounded_edit_distance(&a, &b, 3)
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(distance("paranoia", "paranoia"), Some(0));
+        assert_eq!(distance("paranoa", "paranoia"), Some(1));
+        assert_eq!(distance("parnaoia", "paranoia"), Some(1));
+        assert_eq!(distance("marssalia", "massalia"), Some(1));
+        assert_eq!(distance("", "abc"), Some(3));
+        assert_eq!(distance("kitten", "sitting"), Some(3));
+        assert_eq!(distance("abcdef", "uvwxyz"), None);
+        assert_eq!(distance("a", "abcde"), None);
+    }
+
+    #[test]
+    fn fuzzy_matches() {
+        let config = FuzzyConfig::default();
+        assert!(fuzzy_confidence("paranoia", "paranoa", &config).is_some());
+        assert!(fuzzy_confidence("paranoia", "pranoa", &config).is_none());
+        // Short words have to be exact
+        assert!(fuzzy_confidence("max 300", "mux", &config).is_none());
+        // A missing space
+        assert!(fuzzy_confidence("healing vision", "healingvison", &config).is_some());
+
+        let both = fuzzy_confidence("paranoia max", "paranoa max", &config).unwrap();
+        let one = fuzzy_confidence("paranoia evolution", "paranoa max", &config).unwrap();
+        assert!(both > one);
+        assert!(both < 0.8);
+
+        assert!(fuzzy_confidence("paranoia", "paranoa", &FuzzyConfig::disabled()).is_none());
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::ddr_song::SongId;
+use crate::error::QueryError;
+use crate::website_backends::sanbai::song_details::song_details_url;
+use crate::website_backends::skill_attack::SkillAttackIndex;
+
+/// Something that identifies exactly one song, as opposed to a title that
+/// has to be searched for. Parsed from any of
+/// - a song id like `bIlqP91O9ld1lqlq6qoq9OiPdqIDPP0l`
+/// - a sanbai song details url like
+///   `https://3icecream.com/ddr/song_details/bIlqP91O9ld1lqlq6qoq9OiPdqIDPP0l`
+/// - a Skill Attack music url like `http://skillattack.com/sa4/music.php?index=405`
+/// - a Skill Attack index like `405`
+///
+/// # Examples
+///
+/// ```
+/// use score_websites::search::SongIdentifier;
+///
+/// let url = "https://3icecream.com/ddr/song_details/bIlqP91O9ld1lqlq6qoq9OiPdqIDPP0l";
+/// let id: SongIdentifier = url.parse().unwrap();
+/// assert_eq!(id, "bIlqP91O9ld1lqlq6qoq9OiPdqIDPP0l".parse().unwrap());
+/// assert_eq!("405".parse(), Ok(SongIdentifier::SkillAttackIndex(405)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SongIdentifier {
+    SongId(SongId),
+    SkillAttackIndex(SkillAttackIndex),
+}
+
+impl FromStr for SongIdentifier {
+    type Err = QueryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(song_id) = s.parse() {
+            return Ok(Self::SongId(song_id));
+        }
+        if let Ok(index) = s.parse() {
+            return Ok(Self::SkillAttackIndex(index));
+        }
+
+        let unknown = || QueryError::UnknownIdentifier(s.to_string());
+        let url = s
+            .strip_prefix("https://")
+            .or_else(|| s.strip_prefix("http://"))
+            .ok_or_else(unknown)?;
+        let url = url.strip_prefix("www.").unwrap_or(url);
+        if let Some(rest) = url.strip_prefix("3icecream.com/ddr/song_details/") {
+            let song_id = rest.split(['/', '?', '#']).next().unwrap_or_default();
+            return song_id.parse().map(Self::SongId).map_err(|_| unknown());
+        }
+        if let Some(rest) = url.strip_prefix("skillattack.com/sa4/music.php?") {
+            return rest
+                .split(['&', '#'])
+                .find_map(|param| param.strip_prefix("index="))
+                .and_then(|index| index.parse().ok())
+                .map(Self::SkillAttackIndex)
+                .ok_or_else(unknown);
+        }
+        Err(unknown())
+    }
+}
+
+impl fmt::Display for SongIdentifier {
+    /// The sanbai url for song ids, so it can be opened, and the plain index
+    /// for Skill Attack
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SongIdentifier::SongId(song_id) => write!(f, "{}", song_details_url(song_id)),
+            SongIdentifier::SkillAttackIndex(index) => write!(f, "{}", index),
+        }
+    }
+}
+
+impl From<SongId> for SongIdentifier {
+    fn from(song_id: SongId) -> Self {
+        Self::SongId(song_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_identifiers() {
+        let song_id: SongId = "bIlqP91O9ld1lqlq6qoq9OiPdqIDPP0l".parse().unwrap();
+        for s in [
+            "bIlqP91O9ld1lqlq6qoq9OiPdqIDPP0l",
+            " https://3icecream.com/ddr/song_details/bIlqP91O9ld1lqlq6qoq9OiPdqIDPP0l ",
+            "http://www.3icecream.com/ddr/song_details/bIlqP91O9ld1lqlq6qoq9OiPdqIDPP0l?x=1",
+        ] {
+            assert_eq!(s.parse(), Ok(SongIdentifier::SongId(song_id.clone())));
+        }
+        for s in [
+            "405",
+            "http://skillattack.com/sa4/music.php?index=405",
+            "https://skillattack.com/sa4/music.php?a=1&index=405#top",
+        ] {
+            assert_eq!(s.parse(), Ok(SongIdentifier::SkillAttackIndex(405)));
+        }
+        for s in [
+            "paranoia",
+            "https://3icecream.com/ddr/song_details/nope",
+            "http://skillattack.com/sa4/music.php?index=x",
+            "https://example.com/405",
+        ] {
+            assert_eq!(
+                s.parse::<SongIdentifier>(),
+                Err(QueryError::UnknownIdentifier(s.to_string()))
+            );
+        }
+        let identifier = SongIdentifier::SongId(song_id);
+        assert_eq!(identifier.to_string().parse(), Ok(identifier));
+    }
+}
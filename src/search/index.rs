@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use super::SongIdentifier;
+use crate::ddr_song::{DDRSong, SongId};
+use crate::website_backends::skill_attack::SkillAttackIndex;
+
+/// A trigram index over the search names of a song list, so a search only
+/// has to score the few songs that could contain the query instead of every
+/// song.
+///
+/// The index refers to songs by their position in the list it was built
+/// from, so it has to be rebuilt whenever the song list changes
+#[derive(Debug, Clone, Default)]
+pub struct SearchIndex {
+    /// Every trigram of every search name, and the songs with a name containing it.
+    /// The song lists are sorted and have no duplicates
+    trigrams: HashMap<[char; 3], Vec<usize>>,
+    by_song_id: HashMap<SongId, usize>,
+    by_skill_attack_index: HashMap<SkillAttackIndex, usize>,
+    num_songs: usize,
+}
+
+impl SearchIndex {
+    pub fn new(songs: &[DDRSong]) -> Self {
+        let mut trigrams: HashMap<[char; 3], Vec<usize>> = HashMap::new();
+        let mut by_song_id = HashMap::with_capacity(songs.len());
+        let mut by_skill_attack_index = HashMap::with_capacity(songs.len());
+        for (i, song) in songs.iter().enumerate() {
+            by_song_id.insert(song.song_id.clone(), i);
+            if let Some(index) = song.skill_attack_index {
+                by_skill_attack_index.insert(index, i);
+            }
+            for name in &song.search_names {
+                for trigram in trigrams_of(name) {
+                    let songs = trigrams.entry(trigram).or_default();
+                    // Songs are added in order, so only the last one can be a duplicate
+                    if songs.last() != Some(&i) {
+                        songs.push(i);
+                    }
+                }
+            }
+        }
+        Self {
+            trigrams,
+            by_song_id,
+            by_skill_attack_index,
+            num_songs: songs.len(),
+        }
+    }
+
+    /// The number of songs in the song list the index was built from
+    pub fn len(&self) -> usize {
+        self.num_songs
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num_songs == 0
+    }
+
+    /// The position of the identified song in the song list, if it's in it
+    pub fn position(&self, identifier: &SongIdentifier) -> Option<usize> {
+        match identifier {
+            SongIdentifier::SongId(song_id) => self.by_song_id.get(song_id).copied(),
+            SongIdentifier::SkillAttackIndex(index) => {
+                self.by_skill_attack_index.get(index).copied()
+            }
+        }
+    }
+
+    /// The positions of the songs that could have a name containing every
+    /// word of any of the queries, in order. `None` if any query only has
+    /// words too short to narrow down, so every song could match
+    pub fn candidates(&self, queries: &[String]) -> Option<Vec<usize>> {
+        let mut all = vec![];
+        for query in queries {
+            let mut query_candidates: Option<Vec<usize>> = None;
+            for trigram in query.split_whitespace().flat_map(trigrams_of) {
+                let songs = self.trigrams.get(&trigram).map_or(&[][..], Vec::as_slice);
+                query_candidates = Some(match query_candidates {
+                    None => songs.to_vec(),
+                    Some(candidates) => intersect(&candidates, songs),
+                });
+            }
+            all.extend(query_candidates?);
+        }
+        all.sort_unstable();
+        all.dedup();
+        Some(all)
+    }
+}
+
+fn trigrams_of(text: &str) -> impl Iterator<Item = [char; 3]> {
+    let chars: Vec<char> = text.chars().collect();
+    (0..chars.len().saturating_sub(2)).map(move |i| [chars[i], chars[i + 1], chars[i + 2]])
+}
+
+/// The intersection of two sorted lists
+fn intersect(a: &[usize], b: &[usize]) -> Vec<usize> {
+    let mut out = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                out.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    out
+}
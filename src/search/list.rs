@@ -0,0 +1,187 @@
+use std::ops::RangeInclusive;
+
+use crate::ddr_song::{Bpm, Chart, DDRSong, SongId};
+use crate::error::ListError;
+use crate::filter::ChartFilter;
+
+/// A query returning every song that fits, rather than the one song that
+/// best matches a title. Used to build warm-up sets like "every 14 to 16" or
+/// "songs around the BPM of PARANOiA"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListQuery {
+    /// Every chart within the level range, lowest level first
+    Levels(RangeInclusive<u8>),
+    /// Every song whose main, lowest or highest BPM is within the range,
+    /// slowest first
+    Bpms(RangeInclusive<u16>),
+    /// Every other song whose main BPM is at most `band` away from the main
+    /// BPM of this song, closest first
+    BpmBand { song_id: SongId, band: u16 },
+}
+
+/// A song found by a [`ListQuery`] with its charts that fit the query
+#[derive(Debug, Clone)]
+pub struct ListResult<'ddr_song> {
+    pub song: &'ddr_song DDRSong,
+    pub charts: Vec<(Chart, u8)>,
+}
+
+impl ListQuery {
+    /// Runs the query over the song list. Only charts that also pass `filter`
+    /// are returned, so it can be used to narrow down to singles, doubles or
+    /// certain versions, and songs left without any chart are left out
+    pub fn run<'a>(
+        &self,
+        songs: &'a [DDRSong],
+        filter: &ChartFilter,
+    ) -> Result<Vec<ListResult<'a>>, ListError> {
+        let results = |song_matches: &dyn Fn(&DDRSong) -> bool| {
+            songs
+                .iter()
+                .filter(|song| song_matches(song))
+                .map(|song| ListResult {
+                    song,
+                    charts: song
+                        .ratings
+                        .charts()
+                        .filter(|&(chart, level)| {
+                            filter.matches(song, chart, level)
+                                && match self {
+                                    ListQuery::Levels(levels) => levels.contains(&level),
+                                    _ => true,
+                                }
+                        })
+                        .collect(),
+                })
+                .filter(|result| !result.charts.is_empty())
+                .collect::<Vec<_>>()
+        };
+
+        let results = match self {
+            ListQuery::Levels(_) => {
+                let mut results = results(&|_| true);
+                for result in &mut results {
+                    result.charts.sort_by_key(|&(_, level)| level);
+                }
+                results.sort_by_key(|result| result.charts[0].1);
+                results
+            }
+            ListQuery::Bpms(bpms) => {
+                let mut results =
+                    results(&|song| song.bpm.is_some_and(|bpm| bpm_in_range(bpm, bpms)));
+                results.sort_by_key(|result| result.song.bpm.map(|bpm| bpm.get_main_bpm()));
+                results
+            }
+            ListQuery::BpmBand { song_id, band } => {
+                let song = songs
+                    .iter()
+                    .find(|song| song.song_id == *song_id)
+                    .ok_or_else(|| ListError::UnknownSong(song_id.clone()))?;
+                let target = song
+                    .bpm
+                    .ok_or_else(|| ListError::UnknownBpm(song.song_name.clone()))?
+                    .get_main_bpm();
+                let distance = |song: &DDRSong| {
+                    song.bpm
+                        .map(|bpm| bpm.get_main_bpm().abs_diff(target))
+                        .filter(|&distance| distance <= *band)
+                };
+                let mut results =
+                    results(&|song| song.song_id != *song_id && distance(song).is_some());
+                results.sort_by_key(|result| distance(result.song));
+                results
+            }
+        };
+        Ok(results)
+    }
+}
+
+fn bpm_in_range(bpm: Bpm, range: &RangeInclusive<u16>) -> bool {
+    match bpm {
+        Bpm::Constant(bpm) => range.contains(&bpm),
+        Bpm::Range { lower, upper, main } => {
+            [main, lower, upper].iter().any(|bpm| range.contains(bpm))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ddr_song::PlayStyle;
+    use crate::test_util::songs;
+
+    fn names(results: &[ListResult]) -> Vec<String> {
+        results.iter().map(|r| r.song.song_name.clone()).collect()
+    }
+
+    #[test]
+    fn level_range() {
+        let songs = songs();
+        let results = ListQuery::Levels(14..=16)
+            .run(&songs, &ChartFilter::default())
+            .unwrap();
+        assert_eq!(names(&results), ["PARANOiA", "MAX 300", "888"]);
+        assert_eq!(results[0].charts, [(Chart::ESP, 14), (Chart::EDP, 14)]);
+        assert_eq!(
+            results[1].charts,
+            [
+                (Chart::DSP, 14),
+                (Chart::DDP, 14),
+                (Chart::ESP, 16),
+                (Chart::EDP, 16)
+            ]
+        );
+
+        let singles = ChartFilter {
+            play_style: Some(PlayStyle::Singles),
+            ..Default::default()
+        };
+        let results = ListQuery::Levels(17..=19).run(&songs, &singles).unwrap();
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn bpm_range() {
+        let songs = songs();
+        let run = |range| {
+            names(
+                &ListQuery::Bpms(range)
+                    .run(&songs, &ChartFilter::default())
+                    .unwrap(),
+            )
+        };
+        assert_eq!(run(170..=300), ["PARANOiA", "MAX 300"]);
+        // The lowest and highest BPM of a song count too
+        assert_eq!(run(80..=100), ["PARANOiA"]);
+        assert_eq!(run(350..=400), ["PARANOiA"]);
+        assert!(run(400..=500).is_empty());
+    }
+
+    #[test]
+    fn bpm_band() {
+        let songs = songs();
+        let run = |song_id: &str, band| {
+            ListQuery::BpmBand {
+                song_id: song_id.parse().unwrap(),
+                band,
+            }
+            .run(&songs, &ChartFilter::default())
+            .map(|results| names(&results))
+        };
+        // MAX 300 is 120 away from the main BPM of PARANOiA
+        assert_eq!(
+            run("0bq9qI9PoPIlQl89bDO60o9q8I1iIP66", 120),
+            Ok(vec!["PARANOiA".into()])
+        );
+        assert_eq!(run("0bq9qI9PoPIlQl89bDO60o9q8I1iIP66", 119), Ok(vec![]));
+        assert_eq!(
+            run("ld6P1lbb0bPO9doqbbPOoPb8qoDo8id0", 10),
+            Err(ListError::UnknownBpm("888".into()))
+        );
+        assert!(matches!(
+            run("qqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqq", 10),
+            Err(ListError::UnknownSong(_))
+        ));
+    }
+}
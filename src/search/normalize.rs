@@ -0,0 +1,270 @@
+//! Normalization of song names and queries, so Japanese titles can be
+//! searched in hiragana, katakana or romaji, and full width or accented
+//! characters don't have to be typed exactly.
+//!
+//! ```
+//! use score_websites::search::normalize::{kana_to_romaji, normalize_search_text};
+//!
+//! assert_eq!(normalize_search_text("エンジェル"), "えんじぇる");
+//! assert_eq!(normalize_search_text("ＡＢＣ Café"), "abc cafe");
+//! assert_eq!(kana_to_romaji("えんじぇる"), "enjeru");
+//! ```
+
+use std::collections::HashMap;
+
+use once_cell::sync::Lazy;
+
+/// Hiragana and their Hepburn romaji. Where several kana have the same
+/// romaji, the common one comes first so converting back picks it
+#[rustfmt::skip]
+const KANA_ROMAJI: &[(&str, &str)] = &[
+    ("きゃ", "kya"), ("きゅ", "kyu"), ("きょ", "kyo"),
+    ("しゃ", "sha"), ("しゅ", "shu"), ("しょ", "sho"), ("しぇ", "she"),
+    ("ちゃ", "cha"), ("ちゅ", "chu"), ("ちょ", "cho"), ("ちぇ", "che"),
+    ("にゃ", "nya"), ("にゅ", "nyu"), ("にょ", "nyo"),
+    ("ひゃ", "hya"), ("ひゅ", "hyu"), ("ひょ", "hyo"),
+    ("みゃ", "mya"), ("みゅ", "myu"), ("みょ", "myo"),
+    ("りゃ", "rya"), ("りゅ", "ryu"), ("りょ", "ryo"),
+    ("ぎゃ", "gya"), ("ぎゅ", "gyu"), ("ぎょ", "gyo"),
+    ("じゃ", "ja"), ("じゅ", "ju"), ("じょ", "jo"), ("じぇ", "je"),
+    ("びゃ", "bya"), ("びゅ", "byu"), ("びょ", "byo"),
+    ("ぴゃ", "pya"), ("ぴゅ", "pyu"), ("ぴょ", "pyo"),
+    ("てぃ", "ti"), ("でぃ", "di"), ("とぅ", "tu"), ("どぅ", "du"),
+    ("ふぁ", "fa"), ("ふぃ", "fi"), ("ふぇ", "fe"), ("ふぉ", "fo"),
+    ("うぃ", "wi"), ("うぇ", "we"), ("うぉ", "wo"), ("つぁ", "tsa"),
+    ("ゔぁ", "va"), ("ゔぃ", "vi"), ("ゔぇ", "ve"), ("ゔぉ", "vo"),
+    ("あ", "a"), ("い", "i"), ("う", "u"), ("え", "e"), ("お", "o"),
+    ("か", "ka"), ("き", "ki"), ("く", "ku"), ("け", "ke"), ("こ", "ko"),
+    ("さ", "sa"), ("し", "shi"), ("す", "su"), ("せ", "se"), ("そ", "so"),
+    ("た", "ta"), ("ち", "chi"), ("つ", "tsu"), ("て", "te"), ("と", "to"),
+    ("な", "na"), ("に", "ni"), ("ぬ", "nu"), ("ね", "ne"), ("の", "no"),
+    ("は", "ha"), ("ひ", "hi"), ("ふ", "fu"), ("へ", "he"), ("ほ", "ho"),
+    ("ま", "ma"), ("み", "mi"), ("む", "mu"), ("め", "me"), ("も", "mo"),
+    ("や", "ya"), ("ゆ", "yu"), ("よ", "yo"),
+    ("ら", "ra"), ("り", "ri"), ("る", "ru"), ("れ", "re"), ("ろ", "ro"),
+    ("わ", "wa"), ("を", "o"), ("ゐ", "i"), ("ゑ", "e"), ("ん", "n"),
+    ("が", "ga"), ("ぎ", "gi"), ("ぐ", "gu"), ("げ", "ge"), ("ご", "go"),
+    ("ざ", "za"), ("じ", "ji"), ("ず", "zu"), ("ぜ", "ze"), ("ぞ", "zo"),
+    ("だ", "da"), ("ぢ", "ji"), ("づ", "zu"), ("で", "de"), ("ど", "do"),
+    ("ば", "ba"), ("び", "bi"), ("ぶ", "bu"), ("べ", "be"), ("ぼ", "bo"),
+    ("ぱ", "pa"), ("ぴ", "pi"), ("ぷ", "pu"), ("ぺ", "pe"), ("ぽ", "po"),
+    ("ゔ", "vu"),
+    ("ぁ", "a"), ("ぃ", "i"), ("ぅ", "u"), ("ぇ", "e"), ("ぉ", "o"),
+    ("ゃ", "ya"), ("ゅ", "yu"), ("ょ", "yo"), ("ゎ", "wa"),
+];
+
+/// Other common ways of typing kana in romaji
+#[rustfmt::skip]
+const ROMAJI_ALTERNATIVES: &[(&str, &str)] = &[
+    ("si", "し"), ("ti", "ち"), ("tu", "つ"), ("hu", "ふ"), ("zi", "じ"),
+    ("sya", "しゃ"), ("syu", "しゅ"), ("syo", "しょ"),
+    ("tya", "ちゃ"), ("tyu", "ちゅ"), ("tyo", "ちょ"),
+    ("jya", "じゃ"), ("jyu", "じゅ"), ("jyo", "じょ"),
+];
+
+/// Lowercases, converts full width characters to half width, katakana to
+/// hiragana, and strips accents from latin letters
+pub fn normalize_search_text(text: &str) -> String {
+    text.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            // Full width ascii
+            '！'..='～' => char::from_u32(c as u32 - 0xfee0).unwrap_or(c),
+            '\u{3000}' => ' ',
+            // Katakana to hiragana, the blocks line up
+            'ァ'..='ヶ' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+            _ => strip_diacritic(c),
+        })
+        .collect()
+}
+
+fn strip_diacritic(c: char) -> char {
+    match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' => 'a',
+        'ç' => 'c',
+        'è' | 'é' | 'ê' | 'ë' | 'ē' => 'e',
+        'ì' | 'í' | 'î' | 'ï' | 'ī' => 'i',
+        'ñ' => 'n',
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' => 'o',
+        'ù' | 'ú' | 'û' | 'ü' | 'ū' => 'u',
+        'ý' | 'ÿ' => 'y',
+        _ => c,
+    }
+}
+
+pub fn contains_kana(text: &str) -> bool {
+    text.chars()
+        .any(|c| matches!(c, 'ぁ'..='ゖ' | 'ァ'..='ヺ' | 'ー'))
+}
+
+/// Converts hiragana to Hepburn romaji, leaving everything else as is.
+/// Long vowel marks are dropped, as most people type "dorimu" rather than
+/// "doriimu" for ドリーム
+pub fn kana_to_romaji(text: &str) -> String {
+    static KANA: Lazy<HashMap<&str, &str>> = Lazy::new(|| {
+        let mut map = HashMap::new();
+        for &(kana, romaji) in KANA_ROMAJI {
+            map.entry(kana).or_insert(romaji);
+        }
+        map
+    });
+
+    let mut out = String::with_capacity(text.len());
+    let mut double_next = false;
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == 'っ' {
+            double_next = true;
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        if c == 'ー' {
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        // Try digraphs like きゃ before single kana
+        let two_len = rest.chars().take(2).map(char::len_utf8).sum::<usize>();
+        let (romaji, len) = match KANA.get(&rest[..two_len]) {
+            Some(romaji) if two_len > c.len_utf8() => (Some(*romaji), two_len),
+            _ => (KANA.get(&rest[..c.len_utf8()]).copied(), c.len_utf8()),
+        };
+        match romaji {
+            Some(romaji) => {
+                if double_next {
+                    // Hepburn writes っち as "tchi"
+                    let first = romaji.chars().next().unwrap_or_default();
+                    out.push(if first == 'c' { 't' } else { first });
+                }
+                out.push_str(romaji);
+            }
+            None => out.push(c),
+        }
+        double_next = false;
+        rest = &rest[len..];
+    }
+    out
+}
+
+/// Converts romaji to hiragana. `None` if there are any letters that aren't
+/// part of romaji, like most english words. Anything that isn't a letter is
+/// kept as is
+pub fn romaji_to_kana(text: &str) -> Option<String> {
+    static ROMAJI: Lazy<HashMap<&str, &str>> = Lazy::new(|| {
+        let mut map = HashMap::new();
+        for &(kana, romaji) in KANA_ROMAJI {
+            // Small kana are only ever typed as part of digraphs
+            if !matches!(
+                kana,
+                "ぁ" | "ぃ" | "ぅ" | "ぇ" | "ぉ" | "ゃ" | "ゅ" | "ょ" | "ゎ"
+            ) {
+                map.entry(romaji).or_insert(kana);
+            }
+        }
+        for &(romaji, kana) in ROMAJI_ALTERNATIVES {
+            map.entry(romaji).or_insert(kana);
+        }
+        map
+    });
+
+    let is_vowel = |c: u8| matches!(c, b'a' | b'i' | b'u' | b'e' | b'o');
+    let text = text.to_lowercase();
+    let bytes = text.as_bytes();
+    let mut out = String::with_capacity(text.len() * 3);
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if !c.is_ascii_alphabetic() {
+            // Everything in the table is ascii, so this is a char boundary
+            let ch = text[i..].chars().next()?;
+            if ch.is_alphabetic() {
+                return None;
+            }
+            out.push(ch);
+            i += ch.len_utf8();
+            continue;
+        }
+        // A doubled consonant is a small tsu, e.g. "kk" in "rokku" or "tch" in "matcha"
+        let next = bytes.get(i + 1).copied();
+        if c != b'n' && !is_vowel(c) && (next == Some(c) || (c == b't' && next == Some(b'c'))) {
+            out.push('っ');
+            i += 1;
+            continue;
+        }
+        let kana = (1..=3)
+            .rev()
+            .filter(|len| i + len <= bytes.len())
+            .find_map(|len| ROMAJI.get(&text[i..i + len]).map(|kana| (kana, len)));
+        let (kana, len) = kana?;
+        out.push_str(kana);
+        i += len;
+    }
+    Some(out)
+}
+
+/// The forms of a song name to search, the normalized name and its romaji
+/// if it has any kana
+pub fn name_search_forms(name: &str) -> Vec<String> {
+    let normalized = normalize_search_text(name);
+    let mut forms = vec![];
+    if contains_kana(&normalized) {
+        forms.push(kana_to_romaji(&normalized));
+    }
+    forms.insert(0, normalized);
+    forms
+}
+
+/// The forms of a query to search with. As well as the forms of
+/// [`name_search_forms`], romaji queries are also converted to kana
+pub fn query_search_forms(query: &str) -> Vec<String> {
+    let mut forms = name_search_forms(query);
+    if let Some(kana) = romaji_to_kana(&forms[0]) {
+        if kana != forms[0] {
+            forms.push(kana);
+        }
+    }
+    forms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_text() {
+        assert_eq!(normalize_search_text("エンジェル"), "えんじぇる");
+        assert_eq!(normalize_search_text("ヴァンパイア"), "ゔぁんぱいあ");
+        assert_eq!(normalize_search_text("ＭＡＸ　３００"), "max 300");
+        assert_eq!(normalize_search_text("Pokémon Ōsaka"), "pokemon osaka");
+        assert_eq!(normalize_search_text("漢字"), "漢字");
+    }
+
+    #[test]
+    fn kana_and_romaji() {
+        let cases = [
+            ("えんじぇる", "enjeru"),
+            ("とうきょう", "toukyou"),
+            ("ろっく", "rokku"),
+            ("まっちゃ", "matcha"),
+            ("どりーむ", "dorimu"),
+            ("こんにちは", "konnichiha"),
+        ];
+        for (kana, romaji) in cases {
+            assert_eq!(kana_to_romaji(kana), romaji);
+        }
+        for (kana, romaji) in &cases[..4] {
+            assert_eq!(romaji_to_kana(romaji).as_deref(), Some(*kana));
+        }
+        assert_eq!(romaji_to_kana("shinkai 2").as_deref(), Some("しんかい 2"));
+        assert_eq!(romaji_to_kana("tsutomu").as_deref(), Some("つとむ"));
+        assert_eq!(romaji_to_kana("max"), None);
+        assert_eq!(romaji_to_kana("ぱ"), None);
+    }
+
+    #[test]
+    fn search_forms() {
+        assert_eq!(name_search_forms("エンジェル"), ["えんじぇる", "enjeru"]);
+        assert_eq!(name_search_forms("MAX 300"), ["max 300"]);
+        assert_eq!(query_search_forms("enjeru"), ["enjeru", "えんじぇる"]);
+        assert_eq!(query_search_forms("えんじぇる"), ["えんじぇる", "enjeru"]);
+    }
+}
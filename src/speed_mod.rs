@@ -0,0 +1,234 @@
+use std::fmt;
+
+use crate::courses::Course;
+use crate::ddr_song::Bpm;
+
+/// A speed mod multiplier like x1.75, stored in hundredths so it is exact
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SpeedMod(u16);
+
+impl SpeedMod {
+    pub fn from_hundredths(hundredths: u16) -> Self {
+        Self(hundredths)
+    }
+
+    pub fn hundredths(&self) -> u16 {
+        self.0
+    }
+
+    pub fn multiplier(&self) -> f32 {
+        self.0 as f32 / 100.0
+    }
+
+    /// How fast the arrows scroll at `bpm` with this mod, rounded to the nearest whole number
+    pub fn read_speed(&self, bpm: u16) -> u16 {
+        let read_speed = (bpm as u32 * self.0 as u32 + 50) / 100;
+        read_speed.min(u16::MAX as u32) as u16
+    }
+}
+
+impl fmt::Display for SpeedMod {
+    /// Writes mods like "x1.75", "x1.5" and "x2"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let whole = self.0 / 100;
+        match self.0 % 100 {
+            0 => write!(f, "x{}", whole),
+            frac if frac % 10 == 0 => write!(f, "x{}.{}", whole, frac / 10),
+            frac => write!(f, "x{}.{:02}", whole, frac),
+        }
+    }
+}
+
+/// The speed mods available in game to pick from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpeedMods {
+    mods: Vec<SpeedMod>,
+}
+
+impl Default for SpeedMods {
+    /// x0.25 to x8 in steps of 0.25
+    fn default() -> Self {
+        Self::new((25..=800).step_by(25).map(SpeedMod::from_hundredths))
+    }
+}
+
+/// The recommended mod for a song, and the read speeds it gives
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SpeedRecommendation {
+    pub speed_mod: SpeedMod,
+    pub main_read_speed: u16,
+    pub lower_read_speed: u16,
+    pub upper_read_speed: u16,
+}
+
+impl SpeedRecommendation {
+    fn new(speed_mod: SpeedMod, bpm: Bpm) -> Self {
+        let (lower, upper) = match bpm {
+            Bpm::Constant(bpm) => (bpm, bpm),
+            Bpm::Range { lower, upper, .. } => (lower, upper),
+        };
+        Self {
+            speed_mod,
+            main_read_speed: speed_mod.read_speed(bpm.get_main_bpm()),
+            lower_read_speed: speed_mod.read_speed(lower),
+            upper_read_speed: speed_mod.read_speed(upper),
+        }
+    }
+}
+
+/// A single mod for a whole course, and the read speeds it gives for each song.
+/// Songs whose BPM we don't know are `None`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CourseSpeedRecommendation {
+    pub speed_mod: SpeedMod,
+    pub songs: Vec<Option<SpeedRecommendation>>,
+}
+
+impl SpeedMods {
+    pub fn new(mods: impl IntoIterator<Item = SpeedMod>) -> Self {
+        let mut mods: Vec<_> = mods.into_iter().collect();
+        mods.sort();
+        mods.dedup();
+        Self { mods }
+    }
+
+    pub fn mods(&self) -> &[SpeedMod] {
+        &self.mods
+    }
+
+    /// Recommends the mod that makes the main BPM of the song read closest to
+    /// `target_read_speed`, preferring the slower mod when two are equally close.
+    /// `None` if there are no mods to pick from
+    pub fn recommend(&self, target_read_speed: u16, bpm: Bpm) -> Option<SpeedRecommendation> {
+        let main = bpm.get_main_bpm();
+        let speed_mod = self.closest_mod(|m| m.read_speed(main).abs_diff(target_read_speed))?;
+        Some(SpeedRecommendation::new(speed_mod, bpm))
+    }
+
+    /// Recommends a single mod for every song in `bpms`, keeping the main BPM
+    /// of the song furthest from `target_read_speed` as close to it as possible.
+    /// `None` if there are no mods to pick from or no known BPMs
+    pub fn recommend_for_bpms(
+        &self,
+        target_read_speed: u16,
+        bpms: &[Option<Bpm>],
+    ) -> Option<CourseSpeedRecommendation> {
+        let mains: Vec<u16> = bpms.iter().flatten().map(Bpm::get_main_bpm).collect();
+        if mains.is_empty() {
+            return None;
+        }
+        let speed_mod = self.closest_mod(|m| {
+            mains
+                .iter()
+                .map(|&main| m.read_speed(main).abs_diff(target_read_speed))
+                .max()
+                .unwrap_or_default()
+        })?;
+        Some(CourseSpeedRecommendation {
+            speed_mod,
+            songs: bpms
+                .iter()
+                .map(|bpm| bpm.map(|bpm| SpeedRecommendation::new(speed_mod, bpm)))
+                .collect(),
+        })
+    }
+
+    /// Recommends a single mod for every song of the course, see
+    /// [`SpeedMods::recommend_for_bpms`]
+    pub fn recommend_for_course(
+        &self,
+        target_read_speed: u16,
+        course: &Course,
+    ) -> Option<CourseSpeedRecommendation> {
+        let bpms: Vec<_> = course
+            .songs
+            .iter()
+            .map(|song| song.as_ref().and_then(|song| song.bpm))
+            .collect();
+        self.recommend_for_bpms(target_read_speed, &bpms)
+    }
+
+    /// The mod with the smallest distance, the slowest one on ties
+    fn closest_mod(&self, distance: impl Fn(SpeedMod) -> u16) -> Option<SpeedMod> {
+        // `min_by_key` returns the first of equal elements, and mods are sorted
+        self.mods.iter().copied().min_by_key(|&m| distance(m))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speed_mod_display() {
+        let display = |h| SpeedMod::from_hundredths(h).to_string();
+        assert_eq!(display(175), "x1.75");
+        assert_eq!(display(150), "x1.5");
+        assert_eq!(display(200), "x2");
+        assert_eq!(display(25), "x0.25");
+    }
+
+    #[test]
+    fn recommend_song_mod() {
+        let mods = SpeedMods::default();
+        assert_eq!(mods.mods().len(), 32);
+
+        // 150 * 3.25 = 487.5 and 150 * 3.5 = 525, 3.25 is closer to 500
+        let rec = mods.recommend(500, Bpm::Constant(150)).unwrap();
+        assert_eq!(rec.speed_mod, SpeedMod::from_hundredths(325));
+        assert_eq!(rec.main_read_speed, 488);
+        assert_eq!(rec.lower_read_speed, rec.upper_read_speed);
+
+        let rec = mods
+            .recommend(
+                600,
+                Bpm::Range {
+                    lower: 75,
+                    upper: 300,
+                    main: 150,
+                },
+            )
+            .unwrap();
+        assert_eq!(rec.speed_mod, SpeedMod::from_hundredths(400));
+        assert_eq!(rec.main_read_speed, 600);
+        assert_eq!(rec.lower_read_speed, 300);
+        assert_eq!(rec.upper_read_speed, 1200);
+
+        // 200 * 2.25 = 450 and 200 * 2.75 = 550 are equally far, pick the slower
+        let rec = SpeedMods::new([275, 225].map(SpeedMod::from_hundredths))
+            .recommend(500, Bpm::Constant(200))
+            .unwrap();
+        assert_eq!(rec.speed_mod, SpeedMod::from_hundredths(225));
+
+        // Very slow songs get the fastest mod
+        let rec = mods.recommend(600, Bpm::Constant(50)).unwrap();
+        assert_eq!(rec.speed_mod, SpeedMod::from_hundredths(800));
+
+        assert_eq!(SpeedMods::new([]).recommend(500, Bpm::Constant(150)), None);
+    }
+
+    #[test]
+    fn recommend_course_mod() {
+        let mods = SpeedMods::default();
+        let bpms = [
+            Some(Bpm::Constant(150)),
+            Some(Bpm::Constant(200)),
+            None,
+            Some(Bpm::Range {
+                lower: 100,
+                upper: 400,
+                main: 200,
+            }),
+        ];
+        let rec = mods.recommend_for_bpms(500, &bpms).unwrap();
+        // x3 gives 450 and 600, x2.75 gives 413 and 550, x2.75 has the
+        // smaller worst case
+        assert_eq!(rec.speed_mod, SpeedMod::from_hundredths(275));
+        assert_eq!(rec.songs.len(), 4);
+        assert_eq!(rec.songs[2], None);
+        assert_eq!(rec.songs[0].unwrap().main_read_speed, 413);
+        assert_eq!(rec.songs[3].unwrap().upper_read_speed, 1100);
+
+        assert_eq!(mods.recommend_for_bpms(500, &[None, None]), None);
+    }
+}
@@ -0,0 +1,112 @@
+//! Songs for the unit tests of every module
+
+use crate::ddr_song::unlock::LockTypeTable;
+use crate::ddr_song::{Bpm, DDRSong};
+use crate::website_backends::sanbai::{DDRVersion, Difficulties, LockTypes, SanbaiSong};
+
+/// A test song, built with [`song`]
+pub(crate) struct SongBuilder {
+    sanbai: SanbaiSong,
+    artist: Option<String>,
+    bpm: Option<Bpm>,
+}
+
+/// A song from version 19 that isn't deleted or locked and has no artist or
+/// BPM, until the builder says otherwise
+pub(crate) fn song(id: &str, name: &str, ratings: [u8; 9]) -> SongBuilder {
+    SongBuilder {
+        sanbai: SanbaiSong {
+            song_id: id.parse().unwrap(),
+            song_name: name.to_string(),
+            alternate_name: None,
+            romanized_name: None,
+            searchable_name: None,
+            alphabet: None,
+            version_num: DDRVersion::from_raw(19),
+            deleted: false,
+            ratings: Difficulties(ratings),
+            lock_types: None,
+        },
+        artist: None,
+        bpm: None,
+    }
+}
+
+/// A valid song id that is different for every `n`
+pub(crate) fn song_id(n: u8) -> String {
+    let alphabet = b"01689DIOPQbdiloq";
+    (0..32)
+        .map(|i| alphabet[((n as usize) >> (i % 8)) % 16] as char)
+        .collect()
+}
+
+/// Three songs with BPMs and levels that are easy to tell apart: PARANOiA
+/// with a 90-360 BPM range, MAX 300 at 300 BPM and 888 without a BPM
+pub(crate) fn songs() -> Vec<DDRSong> {
+    vec![
+        song(
+            "6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q",
+            "PARANOiA",
+            [3, 7, 11, 14, 0, 7, 11, 14, 0],
+        )
+        .version(1)
+        .bpm(Bpm::Range {
+            lower: 90,
+            upper: 360,
+            main: 180,
+        })
+        .build(),
+        song(
+            "0bq9qI9PoPIlQl89bDO60o9q8I1iIP66",
+            "MAX 300",
+            [5, 10, 14, 16, 0, 10, 14, 16, 0],
+        )
+        .version(6)
+        .bpm(Bpm::Constant(300))
+        .build(),
+        song(
+            "ld6P1lbb0bPO9doqbbPOoPb8qoDo8id0",
+            "888",
+            [4, 8, 12, 16, 0, 8, 12, 16, 0],
+        )
+        .build(),
+    ]
+}
+
+impl SongBuilder {
+    pub(crate) fn deleted(mut self) -> Self {
+        self.sanbai.deleted = true;
+        self
+    }
+
+    pub(crate) fn version(mut self, version: u8) -> Self {
+        self.sanbai.version_num = DDRVersion::from_raw(version);
+        self
+    }
+
+    pub(crate) fn bpm(mut self, bpm: Bpm) -> Self {
+        self.bpm = Some(bpm);
+        self
+    }
+
+    /// Raw sanbai lock codes, decoded with the bundled table on build
+    pub(crate) fn lock_types(mut self, lock_types: [i32; 9]) -> Self {
+        self.sanbai.lock_types = Some(LockTypes(lock_types));
+        self
+    }
+
+    pub(crate) fn artist(mut self, artist: &str) -> Self {
+        self.artist = Some(artist.to_string());
+        self
+    }
+
+    pub(crate) fn build(self) -> DDRSong {
+        let mut song = DDRSong::new_from_sanbai_and_skillattack(&self.sanbai, None);
+        song.decode_lock_types(&LockTypeTable::default());
+        if let Some(artist) = self.artist {
+            song.set_artist(artist);
+        }
+        song.bpm = self.bpm;
+        song
+    }
+}
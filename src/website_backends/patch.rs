@@ -1 +1,185 @@
+//! Local patches on top of the song list, for things neither sanbai nor
+//! Skill Attack get right for us, like nicknames players actually search for.
+//!
+//! Patches are written in RON, keyed by song id. Every field is optional:
+//!
+//! ```
+//! use score_websites::website_backends::patch::SongPatches;
+//!
+//! let patches = SongPatches::from_ron(r#"(
+//!     songs: {
+//!         "bIlqP91O9ld1lqlq6qoq9OiPdqIDPP0l": (
+//!             search_names: ["roppongi d"],
+//!             display_name: "ROPPONGI EVOLVED ver.D",
+//!             bpm: Constant(150),
+//!         ),
+//!     },
+//! )"#).unwrap();
+//! assert_eq!(patches.len(), 1);
+//! ```
 
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use ron::extensions::Extensions;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::ddr_song::{Bpm, DDRSong, SongId};
+use crate::error::{Error, Result};
+use crate::search::normalize::name_search_forms;
+
+/// Patches for individual songs, see the [module docs](self) for the format
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SongPatches {
+    #[serde(default)]
+    pub songs: HashMap<SongId, SongPatch>,
+}
+
+/// Everything that can be patched about a song. Fields left out are taken
+/// from the song list as usual
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SongPatch {
+    /// Extra names the song can be searched by
+    pub search_names: Vec<String>,
+    /// Replaces the song name. The original name can still be searched for
+    pub display_name: Option<String>,
+    pub artist: Option<String>,
+    /// Takes priority over the BPM cache, for songs sanbai has wrong
+    pub bpm: Option<Bpm>,
+    pub deleted: Option<bool>,
+}
+
+impl SongPatches {
+    /// Parses patches from RON. `Some` can be left out of optional fields
+    pub fn from_ron(ron: &str) -> Result<Self> {
+        ron::Options::default()
+            .with_default_extension(Extensions::IMPLICIT_SOME)
+            .from_str(ron)
+            .map_err(Error::PatchParseError)
+    }
+
+    /// Loads patches from a RON file. A missing file gives no patches
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let ron = match tokio::fs::read_to_string(path).await {
+            Ok(ron) => ron,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                info!("No song patches at {}, not patching", path.display());
+                return Ok(Self::default());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Self::from_ron(&ron)
+    }
+
+    /// The number of patched songs
+    pub fn len(&self) -> usize {
+        self.songs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.songs.is_empty()
+    }
+
+    /// The patched BPM of a song, if any
+    pub fn bpm(&self, song_id: &SongId) -> Option<Bpm> {
+        self.songs.get(song_id)?.bpm
+    }
+
+    /// Patches every song in the list that has a patch. Patching a song again
+    /// with the same patch changes nothing.
+    ///
+    /// Returns the ids of the patches that aren't in the song list, which
+    /// usually means the song was removed or its id changed
+    pub fn apply(&self, songs: &mut [DDRSong]) -> Vec<SongId> {
+        let mut unused: HashMap<&SongId, &SongPatch> = self.songs.iter().collect();
+        for song in songs {
+            if let Some(patch) = unused.remove(&song.song_id) {
+                patch.apply(song);
+            }
+        }
+        unused.into_keys().cloned().collect()
+    }
+}
+
+impl SongPatch {
+    fn apply(&self, song: &mut DDRSong) {
+        for name in self.search_names.iter().chain(&self.display_name) {
+            for form in name_search_forms(name) {
+                if !song.search_names.contains(&form) {
+                    song.search_names.push(form);
+                }
+            }
+        }
+        if let Some(display_name) = &self.display_name {
+            song.song_name = display_name.clone();
+        }
+        if let Some(artist) = &self.artist {
+            song.set_artist(artist.clone());
+        }
+        if let Some(bpm) = self.bpm {
+            song.bpm = Some(bpm);
+        }
+        if let Some(deleted) = self.deleted {
+            song.deleted = deleted;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::song;
+
+    #[test]
+    fn apply_patches() {
+        let patches = SongPatches::from_ron(
+            r#"(
+                songs: {
+                    "6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q": (
+                        search_names: ["Pippi", "pippi"],
+                        display_name: "PARANOiA (Pippi)",
+                        bpm: Range(lower: 90, upper: 180, main: 180),
+                        deleted: true,
+                    ),
+                    "qqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqq": (artist: "nobody"),
+                },
+            )"#,
+        )
+        .unwrap();
+        let mut songs = [song("6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q", "PARANOiA", [0; 9]).build()];
+
+        let unused = patches.apply(&mut songs);
+        assert_eq!(
+            unused,
+            ["qqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqq".parse().unwrap()]
+        );
+        let song = &songs[0];
+        assert_eq!(song.song_name, "PARANOiA (Pippi)");
+        assert_eq!(song.search_names, ["paranoia", "pippi", "paranoia (pippi)"]);
+        assert_eq!(
+            song.bpm,
+            Some(Bpm::Range {
+                lower: 90,
+                upper: 180,
+                main: 180
+            })
+        );
+        assert!(song.deleted);
+
+        let patched = songs.clone();
+        patches.apply(&mut songs);
+        assert_eq!(songs[0].search_names, patched[0].search_names);
+    }
+
+    #[test]
+    fn invalid_patches() {
+        assert!(SongPatches::from_ron("(songs: {\"not an id\": ()})").is_err());
+        assert!(SongPatches::from_ron("(songs: {}, unknown: 1)").is_err());
+        assert_eq!(SongPatches::from_ron("()").unwrap(), SongPatches::default());
+    }
+}
@@ -1,12 +1,17 @@
 use crate::error::{Error, Result};
 use crate::HttpClient;
-use serde::Deserialize;
-use serde_repr::Deserialize_repr;
+use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::ops::{RangeFrom, RangeInclusive, RangeToInclusive};
 use std::result::Result as StdResult;
+use std::str::FromStr;
 use tracing::info;
 
-use crate::{ddr_song::SongId, scores::LampType};
+use crate::ddr_song::{Chart, SongId};
+use crate::scores::LampType;
+
+/// Parsing of the per song details page
+pub mod song_details;
 
 pub async fn get_sanbai_song_data(http: HttpClient) -> Result<Vec<SanbaiSong>> {
     let url = "https://3icecream.com/js/songdata.js";
@@ -21,7 +26,7 @@ pub async fn get_sanbai_song_data(http: HttpClient) -> Result<Vec<SanbaiSong>> {
 
     info!("Sanbai parse start");
     let songdata: Vec<SanbaiSong> =
-        serde_json::from_str(songdata_js).map_err(|e| Error::SanbaiSongJsonParseError(e))?;
+        serde_json::from_str(songdata_js).map_err(Error::SanbaiSongJsonParseError)?;
     info!("Sanbai parse end");
     Ok(songdata)
 }
@@ -31,10 +36,17 @@ where
     D: serde::Deserializer<'de>,
 {
     let num = <i32>::deserialize(deserializer)?;
-    Ok(match num {
-        1 => true,
-        _ => false,
-    })
+    Ok(matches!(num, 1))
+}
+
+/// The first character of a string, so one odd `alphabet` doesn't stop the
+/// whole song list from parsing. `None` for null or an empty string
+fn first_char<'de, D>(deserializer: D) -> StdResult<Option<char>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s = <Option<String>>::deserialize(deserializer)?;
+    Ok(s.and_then(|s| s.trim().chars().next()))
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -44,8 +56,10 @@ pub struct SanbaiSong {
     pub alternate_name: Option<String>,
     pub romanized_name: Option<String>,
     pub searchable_name: Option<String>,
-    // alphabet sort
-    // pub alphabet: char,
+    /// The title initial the song is sorted under in game, e.g. 'A' or a kana
+    #[serde(default)]
+    #[serde(deserialize_with = "first_char")]
+    pub alphabet: Option<char>,
     pub version_num: DDRVersion,
     #[serde(default)]
     #[serde(deserialize_with = "num_to_bool")]
@@ -58,79 +72,414 @@ pub struct SanbaiSong {
 impl SanbaiSong {
     /// 256x256 jacket
     pub fn get_jacket_url(&self) -> String {
-        let base_url = "https://3icecream.com/img/banners/f/";
-        format!("{}{}.jpg", base_url, self.song_id)
+        jacket_url(&self.song_id, JacketSize::Large)
     }
 
     /// 120x120 jacket
     pub fn get_small_jacket_url(&self) -> String {
-        let base_url = "https://3icecream.com/img/banners/";
-        format!("{}{}.jpg", base_url, self.song_id)
+        jacket_url(&self.song_id, JacketSize::Small)
     }
 
     pub fn has_sp_level(&self, level: u8) -> bool {
-        self.ratings.0[0..5].iter().any(|&x| x == level)
+        self.ratings.0[0..5].contains(&level)
     }
 
     pub fn has_dp_level(&self, level: u8) -> bool {
-        self.ratings.0[5..].iter().any(|&x| x == level)
+        self.ratings.0[5..].contains(&level)
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize_repr)]
-#[repr(u8)]
+/// The sizes sanbai has song jackets in
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum JacketSize {
+    /// 256x256 jacket
+    Large,
+    /// 120x120 jacket
+    Small,
+}
+
+impl JacketSize {
+    pub const ALL: [JacketSize; 2] = [JacketSize::Large, JacketSize::Small];
+}
+
+/// The url of the jacket of a song on sanbai
+pub fn jacket_url(song_id: &SongId, size: JacketSize) -> String {
+    let base_url = match size {
+        JacketSize::Large => "https://3icecream.com/img/banners/f/",
+        JacketSize::Small => "https://3icecream.com/img/banners/",
+    };
+    format!("{}{}.jpg", base_url, song_id)
+}
+
+/// The version of DDR a song first appeared in.
+///
+/// Versions are ordered by release, and sanbai numbers them in release order
+/// too, so a version number this crate doesn't know about yet is kept as
+/// [`DDRVersion::Unknown`] and sorts as the newer version it most likely is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum DDRVersion {
-    #[serde(other)]
-    UnknownVersion,
-    DDRA3 = 19,
-    DDRA20Plus = 18,
-    DDRA20 = 17,
-    DDRA = 16,
-    DDR2014 = 15,
-    DDR2013 = 14,
-    DDRX3 = 13,
-    DDRX2 = 12,
-    DDRX = 11,
-    DDRSuperNOVA2 = 10,
-    DDRSuperNOVA = 9,
-    DDREXTREME = 8,
-    DDRMAX2 = 7,
-    DDRMAX = 6,
-    DDR5thMIX = 5,
-    DDR4thMIX = 4,
-    DDR3rdMIX = 3,
-    DDR2ndMIX = 2,
-    DDR1stMIX = 1,
+    DDRWorld,
+    DDRA3,
+    DDRA20Plus,
+    DDRA20,
+    DDRA,
+    DDR2014,
+    DDR2013,
+    DDRX3,
+    DDRX2,
+    DDRX,
+    DDRSuperNOVA2,
+    DDRSuperNOVA,
+    DDREXTREME,
+    DDRMAX2,
+    DDRMAX,
+    DDR5thMIX,
+    DDR4thMIX,
+    DDR3rdMIX,
+    DDR2ndMIX,
+    DDR1stMIX,
+    /// A version number sanbai uses that we don't know about yet
+    Unknown(u8),
+}
+
+/// Every known version with its sanbai number, short name and full name
+const VERSIONS: [(DDRVersion, u8, &str, &str); 20] = [
+    (
+        DDRVersion::DDR1stMIX,
+        1,
+        "1st",
+        "Dance Dance Revolution 1stMIX",
+    ),
+    (
+        DDRVersion::DDR2ndMIX,
+        2,
+        "2nd",
+        "Dance Dance Revolution 2ndMIX",
+    ),
+    (
+        DDRVersion::DDR3rdMIX,
+        3,
+        "3rd",
+        "Dance Dance Revolution 3rdMIX",
+    ),
+    (
+        DDRVersion::DDR4thMIX,
+        4,
+        "4th",
+        "Dance Dance Revolution 4thMIX",
+    ),
+    (
+        DDRVersion::DDR5thMIX,
+        5,
+        "5th",
+        "Dance Dance Revolution 5thMIX",
+    ),
+    (DDRVersion::DDRMAX, 6, "MAX", "Dance Dance Revolution MAX"),
+    (
+        DDRVersion::DDRMAX2,
+        7,
+        "MAX2",
+        "Dance Dance Revolution MAX2",
+    ),
+    (
+        DDRVersion::DDREXTREME,
+        8,
+        "EXTREME",
+        "Dance Dance Revolution EXTREME",
+    ),
+    (
+        DDRVersion::DDRSuperNOVA,
+        9,
+        "SN",
+        "Dance Dance Revolution SuperNOVA",
+    ),
+    (
+        DDRVersion::DDRSuperNOVA2,
+        10,
+        "SN2",
+        "Dance Dance Revolution SuperNOVA2",
+    ),
+    (DDRVersion::DDRX, 11, "X", "Dance Dance Revolution X"),
+    (DDRVersion::DDRX2, 12, "X2", "Dance Dance Revolution X2"),
+    (
+        DDRVersion::DDRX3,
+        13,
+        "X3",
+        "Dance Dance Revolution X3 VS 2ndMIX",
+    ),
+    (
+        DDRVersion::DDR2013,
+        14,
+        "2013",
+        "Dance Dance Revolution 2013",
+    ),
+    (
+        DDRVersion::DDR2014,
+        15,
+        "2014",
+        "Dance Dance Revolution 2014",
+    ),
+    (DDRVersion::DDRA, 16, "A", "Dance Dance Revolution A"),
+    (DDRVersion::DDRA20, 17, "A20", "Dance Dance Revolution A20"),
+    (
+        DDRVersion::DDRA20Plus,
+        18,
+        "A20 PLUS",
+        "Dance Dance Revolution A20 PLUS",
+    ),
+    (DDRVersion::DDRA3, 19, "A3", "Dance Dance Revolution A3"),
+    (
+        DDRVersion::DDRWorld,
+        20,
+        "WORLD",
+        "DanceDanceRevolution WORLD",
+    ),
+];
+
+impl DDRVersion {
+    /// The newest version this crate knows about
+    pub const CURRENT: DDRVersion = DDRVersion::DDRWorld;
+
+    /// Converts the version number sanbai uses, keeping unknown numbers
+    pub fn from_raw(raw: u8) -> Self {
+        VERSIONS
+            .iter()
+            .find(|(_, r, _, _)| *r == raw)
+            .map(|(v, _, _, _)| *v)
+            .unwrap_or(DDRVersion::Unknown(raw))
+    }
+
+    /// The version number sanbai uses
+    pub fn raw(&self) -> u8 {
+        match self {
+            DDRVersion::Unknown(raw) => *raw,
+            _ => self.info().1,
+        }
+    }
+
+    /// The position of this version in the release order, 1 being 1stMIX
+    pub fn release_order(&self) -> u8 {
+        // sanbai numbers versions in release order
+        self.raw()
+    }
+
+    /// A short name like "A20 PLUS", or "?" for unknown versions
+    pub fn short_name(&self) -> &'static str {
+        match self {
+            DDRVersion::Unknown(_) => "?",
+            _ => self.info().2,
+        }
+    }
+
+    /// `true` if this is the version currently in arcades
+    pub fn is_current(&self) -> bool {
+        *self == Self::CURRENT
+    }
+
+    /// Every known version, oldest first
+    pub fn all() -> impl Iterator<Item = DDRVersion> {
+        VERSIONS.iter().map(|(v, _, _, _)| *v)
+    }
+
+    fn info(&self) -> &'static (DDRVersion, u8, &'static str, &'static str) {
+        VERSIONS
+            .iter()
+            .find(|(v, _, _, _)| v == self)
+            .expect("every known version is in VERSIONS")
+    }
+}
+
+impl PartialOrd for DDRVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DDRVersion {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        // `Unknown` can still be made with the number of a known version, so
+        // it sorts after that version to only be `Equal` when it is `==`
+        let is_unknown = |v: &Self| matches!(v, DDRVersion::Unknown(_));
+        self.release_order()
+            .cmp(&other.release_order())
+            .then_with(|| is_unknown(self).cmp(&is_unknown(other)))
+    }
+}
+
+impl<'de> Deserialize<'de> for DDRVersion {
+    fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Ok(Self::from_raw(u8::deserialize(deserializer)?))
+    }
+}
+
+impl Serialize for DDRVersion {
+    fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_u8(self.raw())
+    }
 }
 
 impl fmt::Display for DDRVersion {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match &self {
-            DDRVersion::DDRA3 => write!(f, "Dance Dance Revolution A3"),
-            DDRVersion::DDRA20Plus => write!(f, "Dance Dance Revolution A20 PLUS"),
-            DDRVersion::DDRA20 => write!(f, "Dance Dance Revolution A20"),
-            DDRVersion::DDRA => write!(f, "Dance Dance Revolution A"),
-            DDRVersion::DDR2014 => write!(f, "Dance Dance Revolution 2014"),
-            DDRVersion::DDR2013 => write!(f, "Dance Dance Revolution 2013"),
-            DDRVersion::DDRX3 => write!(f, "Dance Dance Revolution X3 VS 2ndMIX"),
-            DDRVersion::DDRX2 => write!(f, "Dance Dance Revolution X2"),
-            DDRVersion::DDRX => write!(f, "Dance Dance Revolution X"),
-            DDRVersion::DDRSuperNOVA2 => write!(f, "Dance Dance Revolution SuperNOVA2"),
-            DDRVersion::DDRSuperNOVA => write!(f, "Dance Dance Revolution SuperNOVA"),
-            DDRVersion::DDREXTREME => write!(f, "Dance Dance Revolution EXTREME"),
-            DDRVersion::DDRMAX2 => write!(f, "Dance Dance Revolution MAX2"),
-            DDRVersion::DDRMAX => write!(f, "Dance Dance Revolution MAX"),
-            DDRVersion::DDR5thMIX => write!(f, "Dance Dance Revolution 5thMIX"),
-            DDRVersion::DDR4thMIX => write!(f, "Dance Dance Revolution 4thMIX"),
-            DDRVersion::DDR3rdMIX => write!(f, "Dance Dance Revolution 3rdMIX"),
-            DDRVersion::DDR2ndMIX => write!(f, "Dance Dance Revolution 2ndMIX"),
-            DDRVersion::DDR1stMIX => write!(f, "Dance Dance Revolution 1stMIX"),
-            DDRVersion::UnknownVersion => write!(f, "Unknown Version"),
+        match self {
+            DDRVersion::Unknown(raw) => write!(f, "Unknown Version ({})", raw),
+            _ => write!(f, "{}", self.info().3),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum VersionParseError {
+    #[error("`{0}` isn't a DDR version")]
+    UnknownVersion(String),
+    #[error("The version range `{0}` starts after it ends")]
+    ReversedRange(String),
+}
+
+impl FromStr for DDRVersion {
+    type Err = VersionParseError;
+
+    /// Parses short names like "a20plus", "sn2" or "world", ignoring case,
+    /// spaces and a leading "ddr"
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        let name: String = s
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_lowercase();
+        let name = name.strip_prefix("ddr").unwrap_or(&name);
+        let name = name.strip_suffix("mix").unwrap_or(name);
+        Ok(match name {
+            "1st" | "1" => DDRVersion::DDR1stMIX,
+            "2nd" | "2" => DDRVersion::DDR2ndMIX,
+            "3rd" | "3" => DDRVersion::DDR3rdMIX,
+            "4th" | "4" => DDRVersion::DDR4thMIX,
+            "5th" | "5" => DDRVersion::DDR5thMIX,
+            "max" => DDRVersion::DDRMAX,
+            "max2" => DDRVersion::DDRMAX2,
+            "extreme" | "ex" => DDRVersion::DDREXTREME,
+            "sn" | "supernova" => DDRVersion::DDRSuperNOVA,
+            "sn2" | "supernova2" => DDRVersion::DDRSuperNOVA2,
+            "x" => DDRVersion::DDRX,
+            "x2" => DDRVersion::DDRX2,
+            "x3" | "x3vs2nd" => DDRVersion::DDRX3,
+            "2013" => DDRVersion::DDR2013,
+            "2014" => DDRVersion::DDR2014,
+            "a" => DDRVersion::DDRA,
+            "a20" => DDRVersion::DDRA20,
+            "a20plus" | "a20p" => DDRVersion::DDRA20Plus,
+            "a3" => DDRVersion::DDRA3,
+            "world" => DDRVersion::DDRWorld,
+            _ => return Err(VersionParseError::UnknownVersion(s.to_string())),
+        })
+    }
+}
+
+/// An inclusive range of versions. A missing bound is open ended
+///
+/// # Examples
+///
+/// ```
+/// use score_websites::website_backends::sanbai::{DDRVersion, VersionRange};
+///
+/// let a20_onward = VersionRange::from(DDRVersion::DDRA20..);
+/// assert_eq!("a20+".parse(), Ok(a20_onward));
+/// assert!(a20_onward.contains(DDRVersion::DDRA3));
+/// assert!(a20_onward.contains(DDRVersion::Unknown(30)));
+/// assert!(!a20_onward.contains(DDRVersion::DDR2014));
+///
+/// let x_series: VersionRange = "x-x3".parse().unwrap();
+/// assert!(x_series.contains(DDRVersion::DDRX2));
+/// assert!(!x_series.contains(DDRVersion::DDR2013));
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct VersionRange {
+    pub min: Option<DDRVersion>,
+    pub max: Option<DDRVersion>,
+}
+
+impl VersionRange {
+    /// Only a single version
+    pub fn only(version: DDRVersion) -> Self {
+        Self {
+            min: Some(version),
+            max: Some(version),
+        }
+    }
+
+    pub fn contains(&self, version: DDRVersion) -> bool {
+        self.min.is_none_or(|min| min <= version) && self.max.is_none_or(|max| version <= max)
+    }
+}
+
+impl From<RangeInclusive<DDRVersion>> for VersionRange {
+    fn from(range: RangeInclusive<DDRVersion>) -> Self {
+        Self {
+            min: Some(*range.start()),
+            max: Some(*range.end()),
+        }
+    }
+}
+
+impl From<RangeFrom<DDRVersion>> for VersionRange {
+    fn from(range: RangeFrom<DDRVersion>) -> Self {
+        Self {
+            min: Some(range.start),
+            max: None,
         }
     }
 }
 
-#[derive(Debug, Copy, Clone, Deserialize)]
+impl From<RangeToInclusive<DDRVersion>> for VersionRange {
+    fn from(range: RangeToInclusive<DDRVersion>) -> Self {
+        Self {
+            min: None,
+            max: Some(range.end),
+        }
+    }
+}
+
+impl FromStr for VersionRange {
+    type Err = VersionParseError;
+
+    /// Parses "a20" (only A20), "a20+" (A20 onward), "-a20" (up to A20) and
+    /// "x-a20" (X through A20)
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        let s = s.trim();
+        let parse_bound = |b: &str| -> StdResult<Option<DDRVersion>, VersionParseError> {
+            match b.trim() {
+                "" => Ok(None),
+                b => b.parse().map(Some),
+            }
+        };
+        let range = if let Some(min) = s.strip_suffix('+') {
+            Self {
+                min: Some(min.parse()?),
+                max: None,
+            }
+        } else if let Some((min, max)) = s.split_once('-') {
+            Self {
+                min: parse_bound(min)?,
+                max: parse_bound(max)?,
+            }
+        } else {
+            Self::only(s.parse()?)
+        };
+        match (range.min, range.max) {
+            (Some(min), Some(max)) if min > max => {
+                Err(VersionParseError::ReversedRange(s.to_string()))
+            }
+            _ => Ok(range),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
 pub struct Difficulties(pub [u8; 9]);
 
 impl Difficulties {
@@ -159,9 +508,18 @@ impl Difficulties {
     pub fn doubles_difficulties(&self) -> [u8; 4] {
         self.0[5..].try_into().unwrap()
     }
+
+    /// Every chart the song has along with its level
+    pub fn charts(&self) -> impl Iterator<Item = (Chart, u8)> + '_ {
+        self.0
+            .iter()
+            .enumerate()
+            .filter(|(_, &level)| level > 0)
+            .filter_map(|(i, &level)| Some((Chart::from_index(i)?, level)))
+    }
 }
 
-#[derive(Debug, Copy, Clone, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
 pub struct LockTypes(pub [i32; 9]);
 
 // Sanbai scores
@@ -252,6 +610,74 @@ pub async fn get_sanbai_scores(http: HttpClient, username: &str) -> Result<Vec<S
 mod test {
     use super::*;
 
+    #[test]
+    fn lenient_alphabet() {
+        let alphabets: Vec<Option<char>> = [r#""あ""#, r#""""#, r#""AB""#, "null"]
+            .iter()
+            .map(|alphabet| {
+                let json = format!(
+                    r#"{{"song_id": "6P18lOliIQqIO6Di0PP8iDlDQ01b0o0q", "song_name": "song",
+                        "alphabet": {}, "version_num": 19, "ratings": [0,0,0,0,0,0,0,0,0]}}"#,
+                    alphabet
+                );
+                serde_json::from_str::<SanbaiSong>(&json).unwrap().alphabet
+            })
+            .collect();
+        assert_eq!(alphabets, [Some('あ'), None, Some('A'), None]);
+    }
+
+    #[test]
+    fn ddr_version_raw_values() {
+        let versions: Vec<DDRVersion> = serde_json::from_str("[1, 17, 18, 19, 20, 23]").unwrap();
+        assert_eq!(
+            versions,
+            [
+                DDRVersion::DDR1stMIX,
+                DDRVersion::DDRA20,
+                DDRVersion::DDRA20Plus,
+                DDRVersion::DDRA3,
+                DDRVersion::DDRWorld,
+                DDRVersion::Unknown(23),
+            ]
+        );
+        assert_eq!(
+            serde_json::to_string(&versions).unwrap(),
+            "[1,17,18,19,20,23]"
+        );
+        assert!(DDRVersion::Unknown(23) > DDRVersion::CURRENT);
+        assert!(DDRVersion::DDRX3 < DDRVersion::DDR2013);
+        assert!(DDRVersion::Unknown(19) > DDRVersion::DDRA3);
+        assert!(DDRVersion::Unknown(19) < DDRVersion::DDRWorld);
+        assert_eq!(
+            DDRVersion::Unknown(19).cmp(&DDRVersion::Unknown(19)),
+            std::cmp::Ordering::Equal
+        );
+        assert!(DDRVersion::DDRWorld.is_current());
+        assert_eq!(DDRVersion::DDRA20Plus.short_name(), "A20 PLUS");
+        assert_eq!(DDRVersion::all().count(), 20);
+        assert!(DDRVersion::all().all(|v| DDRVersion::from_raw(v.raw()) == v));
+    }
+
+    #[test]
+    fn parse_versions() {
+        assert_eq!("A20 PLUS".parse(), Ok(DDRVersion::DDRA20Plus));
+        assert_eq!("ddrSuperNOVA2".parse(), Ok(DDRVersion::DDRSuperNOVA2));
+        assert_eq!("5thMIX".parse(), Ok(DDRVersion::DDR5thMIX));
+        assert!("a4".parse::<DDRVersion>().is_err());
+
+        let range: VersionRange = "-x".parse().unwrap();
+        assert!(range.contains(DDRVersion::DDR1stMIX));
+        assert!(!range.contains(DDRVersion::DDRX2));
+        assert_eq!(
+            "a3".parse::<VersionRange>(),
+            Ok(VersionRange::only(DDRVersion::DDRA3))
+        );
+        assert_eq!(
+            "a3-a20".parse::<VersionRange>(),
+            Err(VersionParseError::ReversedRange("a3-a20".into()))
+        );
+    }
+
     #[test]
     fn sanbai_score_entry_deser() {
         let json = r#"{ "scores": [
@@ -0,0 +1,441 @@
+use futures::stream::{self, StreamExt};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::ddr_song::{Bpm, Chart, SongId};
+use crate::{HttpClient, Result};
+
+/// Everything we parse out of a sanbai song details page
+#[derive(Debug, Clone, PartialEq)]
+pub struct SongDetails {
+    /// The BPM shown at the top of the page. `None` if sanbai doesn't
+    /// know the BPM yet
+    pub bpm: Option<Bpm>,
+    /// Any extra BPM spans listed after the main BPM, in page order
+    pub bpm_segments: Vec<BpmSegment>,
+    pub artist: Option<String>,
+    /// The charts listed in the difficulty table, in page order
+    pub charts: Vec<ChartDetails>,
+}
+
+/// A BPM section of a song. Constant sections have `lower == upper`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BpmSegment {
+    pub lower: u16,
+    pub upper: u16,
+}
+
+/// A single row of the difficulty table
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ChartDetails {
+    pub chart: Chart,
+    pub level: u8,
+    pub notes: Option<u16>,
+    pub freeze_arrows: Option<u16>,
+    pub shock_arrows: Option<u16>,
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SongDetailsParseError {
+    #[error("Couldn't find a bpm or a missing bpm marker, the html may have changed")]
+    MissingBpm,
+    #[error("Found the bpm range {lower}-{upper} but no main bpm after it")]
+    MissingMainBpm { lower: u16, upper: u16 },
+    #[error("Expected a single main bpm, found `{0}`")]
+    InvalidMainBpm(String),
+    #[error("Couldn't parse `{0}` as a bpm")]
+    InvalidBpm(String),
+    #[error("Bpm range {lower}-{upper} has its bounds reversed")]
+    ReversedBpmRange { lower: u16, upper: u16 },
+    #[error("Couldn't parse chart difficulty index `{0}`")]
+    InvalidChartIndex(String),
+    #[error("Chart difficulty index {0} isn't a known chart")]
+    UnknownChart(usize),
+    #[error("The chart row for {0:?} is listed more than once")]
+    DuplicateChart(Chart),
+    #[error("The chart row for {0:?} has no level")]
+    MissingLevel(Chart),
+    #[error("Couldn't parse `{value}` as the {field} of {chart:?}")]
+    InvalidNumber {
+        chart: Chart,
+        field: &'static str,
+        value: String,
+    },
+}
+
+/// The url of the sanbai song details page for the given song
+pub fn song_details_url(song_id: &SongId) -> String {
+    format!("https://3icecream.com/ddr/song_details/{}", song_id)
+}
+
+/// Fetches and parses the sanbai song details page of a single song
+pub async fn get_sanbai_song_details(http: HttpClient, song_id: &SongId) -> Result<SongDetails> {
+    let response = http
+        .get(song_details_url(song_id))
+        .send()
+        .await?
+        .text()
+        .await?;
+    Ok(parse_song_details(&response)?)
+}
+
+/// Fetches the song details of every song in `song_ids`, keeping at most
+/// `max_concurrent` requests in flight at once.
+///
+/// Results are returned in the order they complete, and a failure for one
+/// song does not stop the others from being fetched.
+pub async fn get_sanbai_song_details_batch(
+    http: HttpClient,
+    song_ids: impl IntoIterator<Item = SongId>,
+    max_concurrent: usize,
+) -> Vec<(SongId, Result<SongDetails>)> {
+    info!("Fetching sanbai song details");
+    let out: Vec<_> = stream::iter(song_ids)
+        .map(|song_id| {
+            let http = http.clone();
+            async move {
+                let details = get_sanbai_song_details(http, &song_id).await;
+                if let Err(e) = &details {
+                    warn!("Couldn't get song details for {}: {:?}", song_id, e);
+                }
+                (song_id, details)
+            }
+        })
+        .buffer_unordered(max_concurrent.max(1))
+        .collect()
+        .await;
+    info!("Fetched {} sanbai song details", out.len());
+    out
+}
+
+/// Parses the html of a sanbai song details page.
+///
+/// The BPM is given by `<span class="sp-bpm">` elements. The first one is
+/// either a constant BPM like `150`, or a range like `75-528` in which
+/// case the next one is the main BPM. Any remaining ones are extra BPM
+/// segments. Songs sanbai doesn't know the BPM of have a
+/// `<span class="sp-missing-bpm">` instead.
+///
+/// The artist is the contents of `<span class="song-artist">`, and each
+/// chart is a `<tr class="chart-row" data-difficulty="N">` with `chart-level`,
+/// `chart-notes`, `chart-freezes` and `chart-shocks` cells, where `N` is the
+/// same difficulty index used by sanbai's score api.
+///
+/// Only the BPM is required. The artist and charts are extras, so a page
+/// without them, or with a chart table we can't make sense of, still gives
+/// its BPM with no artist and no charts.
+pub fn parse_song_details(html: &str) -> std::result::Result<SongDetails, SongDetailsParseError> {
+    let (bpm, bpm_segments) = parse_bpm(html)?;
+    let charts = parse_charts(html).unwrap_or_else(|e| {
+        warn!("Ignoring the chart table of a song details page: {}", e);
+        vec![]
+    });
+    Ok(SongDetails {
+        bpm,
+        bpm_segments,
+        artist: parse_artist(html),
+        charts,
+    })
+}
+
+fn parse_bpm(
+    html: &str,
+) -> std::result::Result<(Option<Bpm>, Vec<BpmSegment>), SongDetailsParseError> {
+    // Matches strings like this, capturing everything between the tags
+    // "<span class="sp-bpm">75-528</span>"
+    static SP_BPM_FINDER: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#"class="sp-bpm">([^<]*)</span>"#).unwrap());
+
+    let spans: Vec<&str> = SP_BPM_FINDER
+        .captures_iter(html)
+        .map(|cap| cap.get(1).unwrap().as_str())
+        .collect();
+
+    let (first, rest) = match spans.split_first() {
+        Some(x) => x,
+        None => {
+            // Sanity check, we should see a `"sp-missing-bpm"` in the html
+            // if not something may have changed with the html so we should give an error for that
+            return if html.contains(r#""sp-missing-bpm""#) {
+                Ok((None, vec![]))
+            } else {
+                Err(SongDetailsParseError::MissingBpm)
+            };
+        }
+    };
+
+    let BpmSegment { lower, upper } = parse_bpm_span(first)?;
+    let (bpm, rest) = if lower == upper {
+        (Bpm::Constant(lower), rest)
+    } else {
+        let (main, rest) = rest
+            .split_first()
+            .ok_or(SongDetailsParseError::MissingMainBpm { lower, upper })?;
+        let main_segment = parse_bpm_span(main)?;
+        if main_segment.lower != main_segment.upper {
+            return Err(SongDetailsParseError::InvalidMainBpm(main.to_string()));
+        }
+        let main = main_segment.lower;
+        (Bpm::Range { lower, upper, main }, rest)
+    };
+
+    let segments = rest
+        .iter()
+        .map(|s| parse_bpm_span(s))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    Ok((Some(bpm), segments))
+}
+
+/// Parses either `"150"` or `"75-528"`
+fn parse_bpm_span(span: &str) -> std::result::Result<BpmSegment, SongDetailsParseError> {
+    let invalid = || SongDetailsParseError::InvalidBpm(span.to_string());
+    let parse = |s: &str| s.trim().parse::<u16>().map_err(|_| invalid());
+
+    let mut parts = span.trim().splitn(2, '-');
+    let lower = parse(parts.next().ok_or_else(invalid)?)?;
+    let upper = match parts.next() {
+        Some(upper) => parse(upper)?,
+        None => lower,
+    };
+    if lower > upper {
+        return Err(SongDetailsParseError::ReversedBpmRange { lower, upper });
+    }
+    Ok(BpmSegment { lower, upper })
+}
+
+fn parse_artist(html: &str) -> Option<String> {
+    static ARTIST_FINDER: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#"class="song-artist">([^<]*)</span>"#).unwrap());
+
+    let artist = ARTIST_FINDER.captures(html)?.get(1)?.as_str().trim();
+    if artist.is_empty() {
+        None
+    } else {
+        Some(html_escape::decode_html_entities(artist).into_owned())
+    }
+}
+
+fn parse_charts(html: &str) -> std::result::Result<Vec<ChartDetails>, SongDetailsParseError> {
+    static CHART_ROW: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r#"(?s)<tr class="chart-row" data-difficulty="([^"]*)">(.*?)</tr>"#).unwrap()
+    });
+    static CHART_CELL: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r#"<td class="chart-(level|notes|freezes|shocks)">([^<]*)</td>"#).unwrap()
+    });
+
+    let mut charts: Vec<ChartDetails> = vec![];
+    for row in CHART_ROW.captures_iter(html) {
+        let index_str = &row[1];
+        let index = index_str
+            .parse::<usize>()
+            .map_err(|_| SongDetailsParseError::InvalidChartIndex(index_str.to_string()))?;
+        let chart = Chart::from_index(index).ok_or(SongDetailsParseError::UnknownChart(index))?;
+        if charts.iter().any(|c| c.chart == chart) {
+            return Err(SongDetailsParseError::DuplicateChart(chart));
+        }
+
+        let mut level = None;
+        let mut notes = None;
+        let mut freeze_arrows = None;
+        let mut shock_arrows = None;
+        for cell in CHART_CELL.captures_iter(&row[2]) {
+            let field = match &cell[1] {
+                "level" => "level",
+                "notes" => "notes",
+                "freezes" => "freezes",
+                _ => "shocks",
+            };
+            let value = cell[2].trim();
+            // Sanbai leaves counts it doesn't know blank, and uses "-" for
+            // charts that can't have that kind of arrow
+            if value.is_empty() || value == "-" {
+                continue;
+            }
+            let invalid = || SongDetailsParseError::InvalidNumber {
+                chart,
+                field,
+                value: value.to_string(),
+            };
+            match field {
+                "level" => level = Some(value.parse::<u8>().map_err(|_| invalid())?),
+                "notes" => notes = Some(value.parse::<u16>().map_err(|_| invalid())?),
+                "freezes" => freeze_arrows = Some(value.parse::<u16>().map_err(|_| invalid())?),
+                _ => shock_arrows = Some(value.parse::<u16>().map_err(|_| invalid())?),
+            }
+        }
+
+        charts.push(ChartDetails {
+            chart,
+            level: level.ok_or(SongDetailsParseError::MissingLevel(chart))?,
+            notes,
+            freeze_arrows,
+            shock_arrows,
+        });
+    }
+    Ok(charts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_range_bpm_page() {
+        let html = include_str!("../../../fixtures/sanbai_song_details_range.html");
+        let details = parse_song_details(html).unwrap();
+        assert_eq!(
+            details.bpm,
+            Some(Bpm::Range {
+                lower: 75,
+                upper: 300,
+                main: 150
+            })
+        );
+        assert_eq!(
+            details.bpm_segments,
+            [
+                BpmSegment {
+                    lower: 75,
+                    upper: 150
+                },
+                BpmSegment {
+                    lower: 300,
+                    upper: 300
+                },
+            ]
+        );
+        assert_eq!(details.artist.as_deref(), Some("αTypeー2 & NAOKI"));
+        assert_eq!(details.charts.len(), 7);
+        assert_eq!(
+            details.charts[3],
+            ChartDetails {
+                chart: Chart::ESP,
+                level: 16,
+                notes: Some(658),
+                freeze_arrows: Some(15),
+                shock_arrows: None,
+            }
+        );
+        assert_eq!(details.charts[4].chart, Chart::BDP);
+    }
+
+    #[test]
+    fn parse_constant_bpm_page() {
+        let html = include_str!("../../../fixtures/sanbai_song_details_constant.html");
+        let details = parse_song_details(html).unwrap();
+        assert_eq!(details.bpm, Some(Bpm::Constant(300)));
+        assert!(details.bpm_segments.is_empty());
+        assert_eq!(details.artist.as_deref(), Some("Ω"));
+        let csp = details.charts.last().unwrap();
+        assert_eq!(csp.chart, Chart::CSP);
+        assert_eq!(csp.level, 17);
+        assert_eq!(csp.shock_arrows, Some(10));
+    }
+
+    #[test]
+    fn parse_missing_bpm_page() {
+        let html = include_str!("../../../fixtures/sanbai_song_details_missing_bpm.html");
+        let details = parse_song_details(html).unwrap();
+        assert_eq!(details.bpm, None);
+        assert_eq!(details.artist, None);
+        assert_eq!(
+            details.charts,
+            [ChartDetails {
+                chart: Chart::ESP,
+                level: 14,
+                notes: None,
+                freeze_arrows: None,
+                shock_arrows: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn malformed_pages() {
+        use SongDetailsParseError::*;
+        let cases = [
+            ("<html></html>", MissingBpm),
+            (
+                r#"<span class="sp-bpm">75-528</span>"#,
+                MissingMainBpm {
+                    lower: 75,
+                    upper: 528,
+                },
+            ),
+            (
+                r#"<span class="sp-bpm">75-528</span><span class="sp-bpm">100-200</span>"#,
+                InvalidMainBpm("100-200".into()),
+            ),
+            (
+                r#"<span class="sp-bpm">99999</span>"#,
+                InvalidBpm("99999".into()),
+            ),
+            (
+                r#"<span class="sp-bpm">abc</span>"#,
+                InvalidBpm("abc".into()),
+            ),
+            (
+                r#"<span class="sp-bpm">200-100</span>"#,
+                ReversedBpmRange {
+                    lower: 200,
+                    upper: 100,
+                },
+            ),
+        ];
+        for (html, expected) in cases {
+            assert_eq!(parse_song_details(html), Err(expected), "{}", html);
+        }
+    }
+
+    #[test]
+    fn charts_and_artist_are_optional() {
+        let details = parse_song_details(r#"<span class="sp-bpm">150</span>"#).unwrap();
+        assert_eq!(details.bpm, Some(Bpm::Constant(150)));
+        assert_eq!(details.artist, None);
+        assert!(details.charts.is_empty());
+    }
+
+    #[test]
+    fn malformed_chart_tables() {
+        use SongDetailsParseError::*;
+        let cases = [
+            (
+                r#"<tr class="chart-row" data-difficulty="9"><td class="chart-level">4</td></tr>"#,
+                UnknownChart(9),
+            ),
+            (
+                r#"<tr class="chart-row" data-difficulty="x"><td class="chart-level">4</td></tr>"#,
+                InvalidChartIndex("x".into()),
+            ),
+            (
+                r#"<tr class="chart-row" data-difficulty="1"><td class="chart-level">4</td></tr>
+                <tr class="chart-row" data-difficulty="1"><td class="chart-level">4</td></tr>"#,
+                DuplicateChart(Chart::BSP),
+            ),
+            (
+                r#"<tr class="chart-row" data-difficulty="2"><td class="chart-notes">4</td></tr>"#,
+                MissingLevel(Chart::DSP),
+            ),
+            (
+                r#"<tr class="chart-row" data-difficulty="2"><td class="chart-level">four</td></tr>"#,
+                InvalidNumber {
+                    chart: Chart::DSP,
+                    field: "level",
+                    value: "four".into(),
+                },
+            ),
+        ];
+        for (html, expected) in cases {
+            assert_eq!(parse_charts(html), Err(expected), "{}", html);
+
+            // A bad chart table never costs us the bpm
+            let page = format!(r#"<span class="sp-bpm">150</span>{}"#, html);
+            let details = parse_song_details(&page).unwrap();
+            assert_eq!(details.bpm, Some(Bpm::Constant(150)));
+            assert!(details.charts.is_empty());
+        }
+    }
+}
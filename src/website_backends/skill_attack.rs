@@ -89,7 +89,6 @@ pub type SkillAttackScores = HashMap<SkillAttackIndex, Scores>;
 pub async fn get_scores(http: HttpClient, ddr_code: u32) -> Result<SkillAttackScores> {
     info!("Sent SA web request");
 
-    let ddr_code = ddr_code;
     let base = "http://skillattack.com/sa4/dancer_score.php?_=matrix&ddrcode=";
     let url = format!("{}{}", base, ddr_code);
 
@@ -154,7 +153,7 @@ pub fn get_scores_inner(webpage: &str) -> Result<SkillAttackScores> {
             .find(name)
             .ok_or(Error::SkillAttackHtmlParseError(name))
     })
-    .map(|index| index.map(|index| (&webpage[index..]).lines().next().unwrap()))
+    .map(|index| index.map(|index| webpage[index..].lines().next().unwrap()))
     .map(|line| {
         INSIDE_ARRAY
             .captures(line?)
@@ -175,7 +174,7 @@ pub fn get_scores_inner(webpage: &str) -> Result<SkillAttackScores> {
         })
         .collect::<Result<Vec<_>>>()?;
 
-    let scores: Vec<Vec<_>> = (&array_contents[1..10])
+    let scores: Vec<Vec<_>> = array_contents[1..10]
         .iter()
         .map(|s| {
             QUOTED_TEXT
@@ -188,7 +187,7 @@ pub fn get_scores_inner(webpage: &str) -> Result<SkillAttackScores> {
                 .collect::<Result<Vec<_>>>()
         })
         .collect::<Result<Vec<Vec<_>>>>()?;
-    let combo_types: Vec<Vec<_>> = (&array_contents[10..])
+    let combo_types: Vec<Vec<_>> = array_contents[10..]
         .iter()
         .map(|s| {
             s.split(',')